@@ -1,5 +1,6 @@
-BEGIN TRANSACTION;
-DROP TABLE IF EXISTS "chat";
+-- the schema of v01.sql, databases created by it are adopted as version 1.
+-- Its BEGIN, COMMIT and DROP TABLE lines are left out, migrations run inside
+-- a transaction and must never drop data. Change the schema in a new migration
 CREATE TABLE IF NOT EXISTS "chat" (
 	"message_id"	INTEGER NOT NULL UNIQUE,
 	"message"	TEXT,
@@ -8,7 +9,6 @@ CREATE TABLE IF NOT EXISTS "chat" (
 	"to"	TEXT,
 	PRIMARY KEY("message_id")
 );
-DROP TABLE IF EXISTS "users";
 CREATE TABLE IF NOT EXISTS "users" (
     "user_id"	INTEGER NOT NULL UNIQUE,
 	"user_name"	TEXT NOT NULL,
@@ -18,7 +18,6 @@ CREATE TABLE IF NOT EXISTS "users" (
 	"state"	TEXT,
 	PRIMARY KEY("user_name")
 );
-DROP TABLE IF EXISTS "sessions";
 CREATE TABLE IF NOT EXISTS "sessions" (
 	"id"	TEXT NOT NULL UNIQUE,
 	"created"	INTEGER NOT NULL,
@@ -26,4 +25,3 @@ CREATE TABLE IF NOT EXISTS "sessions" (
 	"settings"	TEXT,
 	PRIMARY KEY("id")
 );
-COMMIT;
@@ -0,0 +1,5 @@
+-- chat per session and channel, the game phase of a session.
+-- Messages written before belong to no session and stay in the village channel
+ALTER TABLE "chat" ADD COLUMN "session_id" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "chat" ADD COLUMN "channel" TEXT NOT NULL DEFAULT 'village';
+ALTER TABLE "sessions" ADD COLUMN "phase" TEXT NOT NULL DEFAULT 'lobby';
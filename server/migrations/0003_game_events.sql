@@ -0,0 +1,13 @@
+-- append only log of everything that changed a game, see game_log::replay
+CREATE TABLE IF NOT EXISTS "game_events" (
+	"seq"	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	"session_id"	TEXT NOT NULL,
+	"created"	INTEGER NOT NULL,
+	"kind"	TEXT NOT NULL,
+	"data"	TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS "game_events_session" ON "game_events" ("session_id", "seq");
+CREATE TRIGGER IF NOT EXISTS "game_events_immutable" BEFORE UPDATE ON "game_events"
+BEGIN
+	SELECT RAISE(ABORT, 'game events are immutable');
+END;
@@ -0,0 +1,21 @@
+-- finished games, written when a game ends and kept when the session is deleted
+CREATE TABLE IF NOT EXISTS "archived_games" (
+	"game_id"	INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	"session_id"	TEXT NOT NULL,
+	"started"	INTEGER NOT NULL,
+	"ended"	INTEGER NOT NULL,
+	"winner"	TEXT,
+	"rounds"	INTEGER NOT NULL,
+	UNIQUE("session_id", "started")
+);
+CREATE TABLE IF NOT EXISTS "archived_players" (
+	"game_id"	INTEGER NOT NULL,
+	"user_id"	INTEGER NOT NULL,
+	"user_name"	TEXT NOT NULL,
+	"role"	TEXT,
+	"state"	TEXT NOT NULL,
+	"death_cause"	TEXT,
+	"death_round"	INTEGER,
+	PRIMARY KEY("game_id", "user_id")
+);
+CREATE INDEX IF NOT EXISTS "archived_players_name" ON "archived_players" ("user_name");
@@ -0,0 +1,3 @@
+-- the session list counts players per session and pages by creation date
+CREATE INDEX IF NOT EXISTS "users_session" ON "users" ("session_id");
+CREATE INDEX IF NOT EXISTS "sessions_created" ON "sessions" ("created", "id");
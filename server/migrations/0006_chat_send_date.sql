@@ -0,0 +1,2 @@
+-- the stats count the messages of the last minute
+CREATE INDEX IF NOT EXISTS "chat_send_date" ON "chat" ("send_date");
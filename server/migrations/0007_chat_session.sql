@@ -0,0 +1,2 @@
+-- chat history pages are read per session by message_id
+CREATE INDEX IF NOT EXISTS "chat_session" ON "chat" ("session_id", "message_id");
@@ -0,0 +1,17 @@
+-- v01 made user_name the primary key, so a name could only be used once on the whole
+-- server. Like the memory storage, names only have to be unique within their session.
+-- SQLite can't change a primary key, so the table is copied. The unique constraint
+-- replaces the users_session index, lookups by session_id use it as well
+CREATE TABLE "users_new" (
+	"user_id"	INTEGER NOT NULL UNIQUE,
+	"user_name"	TEXT NOT NULL,
+	"session_id"	TEXT,
+	"role"	TEXT,
+	"joined"	INTEGER,
+	"state"	TEXT,
+	UNIQUE("session_id", "user_name")
+);
+INSERT INTO "users_new" ("user_id", "user_name", "session_id", "role", "joined", "state")
+	SELECT "user_id", "user_name", "session_id", "role", "joined", "state" FROM "users";
+DROP TABLE "users";
+ALTER TABLE "users_new" RENAME TO "users";
@@ -0,0 +1,33 @@
+use crate::api::auth::AdminAuthToken;
+use crate::api::error::ApiError;
+use crate::api::net_types::{ArchivedGame, ArchivedGameInfo};
+use crate::database::Storage;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use std::sync::Arc;
+
+// mounted to /api/v1/games/
+pub fn get_archive_api_routes() -> Vec<Route> {
+    routes![get_games, get_game]
+}
+
+/// finished games of all sessions, newest first. With player set only the games
+/// a player of that name took part in. Admins only, as it spans every session
+#[get("/?<player>", format = "json")]
+fn get_games(
+    player: Option<String>,
+    _auth: AdminAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<ArchivedGameInfo>>, ApiError> {
+    Ok(Json(db.get_archived_games(player.as_deref())?))
+}
+
+/// one finished game with every participant, their role and how they died
+#[get("/<game_id>", format = "json")]
+fn get_game(
+    game_id: i64,
+    _auth: AdminAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<ArchivedGame>, ApiError> {
+    Ok(Json(db.get_archived_game(game_id)?))
+}
@@ -12,6 +12,10 @@ pub struct AdminAuthToken {
 }
 
 impl AdminAuthToken {
+    pub fn exp(&self) -> u64 {
+        self.basic.exp()
+    }
+
     pub fn get_jwt() -> String {
         use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -74,33 +78,29 @@ impl<'a, 'r> request::FromRequest<'a, 'r> for AdminAuthToken {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        match request.headers().get_one("Authorization") {
-            Some(hdr) => {
-                let splitted: Vec<&str> = hdr.split(" ").collect();
-                info!("got Authorization header: {:?}", splitted);
-                if splitted.len() == 2 && splitted[0] == "Bearer" {
-                    match AdminAuthToken::try_from(splitted[1]) {
-                        Ok(at) => return Outcome::Success(at),
-                        _ => {
-                            warn!("Failed to parse auth-token from header");
-                        }
+        if let Some(hdr) = request.headers().get_one("Authorization") {
+            let splitted: Vec<&str> = hdr.split(" ").collect();
+            info!("got Authorization header with scheme {:?}", splitted[0]);
+            if splitted.len() == 2 && splitted[0] == "Bearer" {
+                match AdminAuthToken::try_from(splitted[1]) {
+                    Ok(at) => return Outcome::Success(at),
+                    _ => {
+                        warn!("Failed to parse auth-token from header");
                     }
                 }
             }
-            None => {}
         }
 
         // test if was stored in cookies
-        match request.cookies().get("token") {
-            Some(token_cookie) => match AdminAuthToken::try_from(token_cookie.value()) {
+        if let Some(token_cookie) = request.cookies().get("token") {
+            match AdminAuthToken::try_from(token_cookie.value()) {
                 Ok(at) => return Outcome::Success(at),
                 _ => {
                     warn!("Failed to parse auth-token from cookie");
                 }
-            },
-            None => {}
+            }
         }
 
-        Outcome::Forward(())
+        super::wrong_token(request)
     }
 }
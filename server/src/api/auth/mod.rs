@@ -1,6 +1,8 @@
+use crate::api::error::{ApiError, API_BASE};
 use log::info;
-use rocket::response;
-use rocket::{http, Route, State};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Request, Route, State};
 use rocket_contrib::json;
 use serde::Deserialize;
 
@@ -36,20 +38,42 @@ pub fn get_auth_api_routes() -> Vec<Route> {
 /// nothing? maybe add different levels of control
 ///
 
+/// failure of a guard without a usable token: pages forward to their login redirect,
+/// the api answers 401
+fn no_token<T>(request: &Request) -> Outcome<T, ()> {
+    if request.uri().path().starts_with(API_BASE) {
+        Outcome::Failure((Status::Unauthorized, ()))
+    } else {
+        Outcome::Forward(())
+    }
+}
+
+/// like no_token, but a valid token of another auth_level is answered with 403
+fn wrong_token<T>(request: &Request) -> Outcome<T, ()> {
+    match BasicAuthToken::from_request(request) {
+        Outcome::Success(_) if request.uri().path().starts_with(API_BASE) => {
+            Outcome::Failure((Status::Forbidden, ()))
+        }
+        _ => no_token(request),
+    }
+}
+
 #[get("/status?<token>")]
+#[allow(unused_variables)]
 fn get_status(token: Option<token::BasicAuthToken>) -> String {
     // TODO
     "unimplemented".into()
 }
 
 use crate::api::auth::player_token::PlayerState;
-use crate::database::Database;
+use crate::database::Storage;
 use crate::notify::{Notification, Notifier};
-use serde::export::TryFrom;
+use std::convert::TryFrom;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[derive(Deserialize)]
-struct ConnectData {
+pub(crate) struct ConnectData {
     username: String,
     session_id: String,
 }
@@ -59,9 +83,9 @@ struct ConnectData {
 fn connect_client(
     addr: SocketAddr,
     conn_data: json::Json<ConnectData>,
-    db: State<Database>,
+    db: State<Arc<dyn Storage>>,
     notifier: State<Notifier>,
-) -> response::status::Custom<String> {
+) -> Result<String, ApiError> {
     let conn_data = conn_data.into_inner();
 
     info!(
@@ -73,13 +97,11 @@ fn connect_client(
     // validate session_id
     let sid: SessionID = match SessionID::try_from(conn_data.session_id.as_str()) {
         Ok(sid) => sid,
-        Err(e) => return response::status::Custom(http::Status::BadRequest, e.to_string()),
+        Err(e) => return Err(ApiError::bad_request(e)),
     };
 
-    match Database::maybe_add_player(&mut db.get_locked_conn(), &conn_data.username, &sid) {
+    match db.maybe_add_player(&conn_data.username, &sid) {
         Ok(user_id) => {
-
-
             let jwt = PlayerAuthToken::get_jwt(
                 user_id,
                 sid,
@@ -90,24 +112,23 @@ fn connect_client(
 
             // tell others that new player has connected
             notifier.send(Notification::UpdatePlayerList(sid));
+            // player count of the session changed
+            notifier.send(Notification::UpdateSessionList);
 
-            response::status::Custom(http::Status::Ok, jwt)
+            Ok(jwt)
         }
-        Err(emsg) => return response::status::Custom(http::Status::BadRequest, emsg),
+        Err(e) => Err(e.into()),
     }
 }
 
 #[derive(Deserialize)]
-struct ConnectAdminData {
+pub(crate) struct ConnectAdminData {
     password: String,
 }
 
 /// validates user connection request and if session exists and user is allowed to join, send jwt
 #[post("/connect/ctrl", data = "<conn_data>")]
-fn connect_admin(
-    addr: SocketAddr,
-    conn_data: json::Json<ConnectAdminData>,
-) -> response::status::Custom<String> {
+fn connect_admin(addr: SocketAddr, conn_data: json::Json<ConnectAdminData>) -> String {
     let conn_data = conn_data.into_inner();
 
     info!(
@@ -115,6 +136,5 @@ fn connect_admin(
         addr, &conn_data.password
     );
 
-    let jwt = AdminAuthToken::get_jwt();
-    response::status::Custom(http::Status::Ok, jwt)
+    AdminAuthToken::get_jwt()
 }
@@ -7,6 +7,7 @@ use jsonwebtoken as jwt;
 use log::{info, warn};
 use rocket::http::RawStr;
 use rocket::request::Outcome;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub struct PlayerAuthToken {
@@ -18,6 +19,8 @@ pub struct PlayerAuthToken {
     pub state: String,
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
 pub enum PlayerState {
     Waiting,
     Alive,
@@ -28,10 +31,24 @@ pub enum PlayerState {
 impl PlayerState {
     pub fn as_str(&self) -> &'static str {
         match self {
-            &PlayerState::Waiting => "waiting",
-            &PlayerState::Alive => "alive",
-            &PlayerState::Dead => "dead",
-            &PlayerState::Spectator => "spectator",
+            PlayerState::Waiting => "waiting",
+            PlayerState::Alive => "alive",
+            PlayerState::Dead => "dead",
+            PlayerState::Spectator => "spectator",
+        }
+    }
+}
+
+impl TryFrom<&str> for PlayerState {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "waiting" => Ok(PlayerState::Waiting),
+            "alive" => Ok(PlayerState::Alive),
+            "dead" => Ok(PlayerState::Dead),
+            "spectator" => Ok(PlayerState::Spectator),
+            _ => Err("Unknown player state"),
         }
     }
 }
@@ -78,7 +95,7 @@ impl PlayerAuthToken {
     }
 }
 
-impl<'a> TryFrom<BasicAuthToken> for PlayerAuthToken {
+impl TryFrom<BasicAuthToken> for PlayerAuthToken {
     type Error = &'static str;
 
     fn try_from(value: BasicAuthToken) -> Result<Self, Self::Error> {
@@ -142,33 +159,29 @@ impl<'a, 'r> request::FromRequest<'a, 'r> for PlayerAuthToken {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        match request.headers().get_one("Authorization") {
-            Some(hdr) => {
-                let splitted: Vec<&str> = hdr.split(" ").collect();
-                info!("got Authorization header: {:?}", splitted);
-                if splitted.len() == 2 && splitted[0] == "Bearer" {
-                    match PlayerAuthToken::try_from(splitted[1]) {
-                        Ok(at) => return Outcome::Success(at),
-                        _ => {
-                            warn!("Failed to parse auth-token from header");
-                        }
+        if let Some(hdr) = request.headers().get_one("Authorization") {
+            let splitted: Vec<&str> = hdr.split(" ").collect();
+            info!("got Authorization header with scheme {:?}", splitted[0]);
+            if splitted.len() == 2 && splitted[0] == "Bearer" {
+                match PlayerAuthToken::try_from(splitted[1]) {
+                    Ok(at) => return Outcome::Success(at),
+                    _ => {
+                        warn!("Failed to parse auth-token from header");
                     }
                 }
             }
-            None => {}
         }
 
         // test if was stored in cookies
-        match request.cookies().get("token") {
-            Some(token_cookie) => match PlayerAuthToken::try_from(token_cookie.value()) {
+        if let Some(token_cookie) = request.cookies().get("token") {
+            match PlayerAuthToken::try_from(token_cookie.value()) {
                 Ok(at) => return Outcome::Success(at),
                 _ => {
                     warn!("Failed to parse auth-token from cookie");
                 }
-            },
-            None => {}
+            }
         }
 
-        Outcome::Forward(())
+        super::wrong_token(request)
     }
 }
@@ -1,4 +1,4 @@
-use log::{error, info, warn};
+use log::info;
 use rocket::{http, request};
 use std::convert::TryFrom;
 use std::fmt::Error;
@@ -29,7 +29,7 @@ impl TryFrom<&str> for SessionID {
         }) {
             return Err("session-id must consist of digits and uppercase ascii letters");
         }
-        let mut buf = ['-' as u8; SID_LENGTH];
+        let mut buf = [b'-'; SID_LENGTH];
         for (i, c) in value.chars().enumerate() {
             buf[i] = c as u8;
         }
@@ -65,7 +65,7 @@ impl<'q> request::FromParam<'q> for SessionID {
             return Err("sessionID must by uppercase letters or digits (radix 10)");
         }
 
-        let mut sid_chars = ['-' as u8; SID_LENGTH];
+        let mut sid_chars = [b'-'; SID_LENGTH];
         for (i, c) in sid.chars().enumerate() {
             sid_chars[i] = c as u8;
         }
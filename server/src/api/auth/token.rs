@@ -44,6 +44,11 @@ impl BasicAuthToken {
         self.auth_level
     }
 
+    /// unix timestamp (secs) after which the token is no longer valid
+    pub fn exp(&self) -> u64 {
+        self.exp
+    }
+
     pub fn claims(&self) -> &AuthClaims {
         &self.claims
     }
@@ -85,7 +90,7 @@ impl TryFrom<&str> for BasicAuthToken {
         // try to decode jwt
         let tdata: TokenData<AuthClaims> =
             jwt::decode(value, super::DEV_SECRET.as_bytes(), &Validation::default())
-                .map_err(|e| "JWT decoding failed")?;
+                .map_err(|_| "JWT decoding failed")?;
         tdata.claims.try_into()
     }
 }
@@ -110,33 +115,29 @@ impl<'a, 'r> request::FromRequest<'a, 'r> for BasicAuthToken {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
-        match request.headers().get_one("Authorization") {
-            Some(hdr) => {
-                let splitted: Vec<&str> = hdr.split(" ").collect();
-                info!("got Authorization header: {:?}", splitted);
-                if splitted.len() == 2 && splitted[0] == "Bearer" {
-                    match BasicAuthToken::try_from(splitted[1]) {
-                        Ok(at) => return Outcome::Success(at),
-                        _ => {
-                            warn!("Failed to parse auth-token from header");
-                        }
+        if let Some(hdr) = request.headers().get_one("Authorization") {
+            let splitted: Vec<&str> = hdr.split(" ").collect();
+            info!("got Authorization header with scheme {:?}", splitted[0]);
+            if splitted.len() == 2 && splitted[0] == "Bearer" {
+                match BasicAuthToken::try_from(splitted[1]) {
+                    Ok(at) => return Outcome::Success(at),
+                    _ => {
+                        warn!("Failed to parse auth-token from header");
                     }
                 }
             }
-            None => {}
         }
 
         // test if was stored in cookies
-        match request.cookies().get("token") {
-            Some(token_cookie) => match BasicAuthToken::try_from(token_cookie.value()) {
+        if let Some(token_cookie) = request.cookies().get("token") {
+            match BasicAuthToken::try_from(token_cookie.value()) {
                 Ok(at) => return Outcome::Success(at),
                 _ => {
                     warn!("Failed to parse auth-token from cookie");
                 }
-            },
-            None => {}
+            }
         }
 
-        Outcome::Forward(())
+        super::no_token(request)
     }
 }
@@ -0,0 +1,33 @@
+use crate::api::auth::AdminAuthToken;
+use crate::api::error::ApiError;
+use crate::api::net_types::BackupInfo;
+use crate::backup::{self, BackupConfig};
+use crate::database::Storage;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use std::sync::Arc;
+
+// mounted to /api/v1/backups/
+pub fn get_backup_api_routes() -> Vec<Route> {
+    routes![list_backups, create_backup]
+}
+
+/// admin only: existing backups, newest first. Restoring one is only possible
+/// at startup with `server restore <name>`
+#[get("/", format = "json")]
+fn list_backups(
+    _auth: AdminAuthToken,
+    config: State<BackupConfig>,
+) -> Result<Json<Vec<BackupInfo>>, ApiError> {
+    Ok(Json(config.list()?))
+}
+
+/// admin only: snapshots the running database and deletes the oldest backups
+#[post("/")]
+fn create_backup(
+    _auth: AdminAuthToken,
+    config: State<BackupConfig>,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<BackupInfo>, ApiError> {
+    Ok(Json(backup::create(&**db, &config)?))
+}
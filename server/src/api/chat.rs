@@ -0,0 +1,427 @@
+use crate::api::auth::player_token::PlayerState;
+use crate::api::auth::token::AuthLevel;
+use crate::api::auth::AdminAuthToken;
+use crate::api::auth::{BasicAuthToken, PlayerAuthToken, SessionID};
+use crate::api::error::ApiError;
+use crate::api::net_types::{
+    ChatChannel, ChatMessage, GamePhase, MuteInfo, PlayerData, SessionSettings,
+};
+use crate::database::{ChatQuery, DbError, Storage};
+use crate::moderation::{Moderation, Rejection};
+use crate::notify::{Notification, Notifier};
+use log::{info, warn};
+use rocket::http::Status;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// max length of a chat message in chars, before escaping
+pub const MAX_MESSAGE_LEN: usize = 500;
+
+/// page size of the history if no limit is given
+pub const DEFAULT_PAGE_LEN: u32 = 100;
+
+/// larger pages are cut to this
+const MAX_PAGE_LEN: u32 = 500;
+
+/// role name of the werewolves, the only ones allowed in ChatChannel::Wolves
+pub const WEREWOLF_ROLE: &str = "werwolf";
+
+// mounted to /api/v1/sessions/ next to the session routes
+pub fn get_chat_api_routes() -> Vec<Route> {
+    routes![get_chat, post_chat, get_mutes, mute_player, unmute_player]
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ChatPostData {
+    message: String,
+    channel: Option<ChatChannel>,
+    /// user_id of the recipient, makes the message a whisper
+    to: Option<u32>,
+}
+
+/// escapes the chars that could open or close tags and attributes
+pub fn escape_html(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// who reads or posts, with the current role and state from the database
+pub enum ChatMember {
+    Admin,
+    Player(PlayerData),
+}
+
+impl ChatMember {
+    /// resolves the token to a member of the session, Err if a player token is
+    /// incomplete, belongs to another session or its player isn't in the database anymore
+    fn from_auth(
+        auth: BasicAuthToken,
+        sid: &SessionID,
+        db: &dyn Storage,
+    ) -> Result<ChatMember, ApiError> {
+        if auth.auth_level() == AuthLevel::Control {
+            return Ok(ChatMember::Admin);
+        }
+
+        match PlayerAuthToken::try_from(auth) {
+            Ok(player_auth) if player_auth.session_id == *sid => {
+                match db.get_player(sid, player_auth.user_id) {
+                    Ok(player) => Ok(ChatMember::Player(player)),
+                    Err(DbError::NotFound(_)) => Err(ApiError::forbidden("Player not found")),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Ok(_) => Err(ApiError::forbidden("Not a player of this session")),
+            // never an admin, whatever is missing in the claims
+            Err(_) => Err(ApiError::forbidden("Invalid player token")),
+        }
+    }
+
+    fn user_id(&self) -> Option<u32> {
+        match self {
+            ChatMember::Admin => None,
+            ChatMember::Player(player) => Some(player.user_id),
+        }
+    }
+
+    pub fn can_read(&self, channel: ChatChannel) -> bool {
+        let player = match self {
+            ChatMember::Admin => return true,
+            ChatMember::Player(player) => player,
+        };
+
+        match channel {
+            ChatChannel::Village => true,
+            ChatChannel::Wolves => is_werewolf(player) && is_alive(player),
+            ChatChannel::Dead => !is_alive(player),
+            // depends on the message, see can_read_message
+            ChatChannel::Whisper => false,
+            ChatChannel::System => true,
+        }
+    }
+
+    /// channels the member can read messages of, None for all. Whispers are
+    /// included, only those of the player are readable though
+    fn readable_channels(&self) -> Option<Vec<ChatChannel>> {
+        match self {
+            ChatMember::Admin => None,
+            ChatMember::Player(_) => Some(
+                [
+                    ChatChannel::Village,
+                    ChatChannel::Wolves,
+                    ChatChannel::Dead,
+                    ChatChannel::Whisper,
+                    ChatChannel::System,
+                ]
+                .iter()
+                .copied()
+                .filter(|&channel| channel == ChatChannel::Whisper || self.can_read(channel))
+                .collect(),
+            ),
+        }
+    }
+
+    /// whispers are only readable by sender, recipient and admins
+    pub fn can_read_message(&self, msg: &ChatMessage) -> bool {
+        match (self, msg.channel) {
+            (ChatMember::Player(player), ChatChannel::Whisper) => {
+                msg.sender == Some(player.user_id) || msg.to == Some(player.user_id)
+            }
+            _ => self.can_read(msg.channel),
+        }
+    }
+
+    pub fn can_post(&self, channel: ChatChannel, phase: GamePhase) -> bool {
+        let player = match self {
+            ChatMember::Admin => return true,
+            ChatMember::Player(player) => player,
+        };
+
+        match channel {
+            ChatChannel::Village => is_alive(player) && phase != GamePhase::Night,
+            ChatChannel::Wolves => self.can_read(channel) && phase == GamePhase::Night,
+            ChatChannel::Dead => self.can_read(channel),
+            // the recipient is checked in post_chat, whispers work in every phase
+            ChatChannel::Whisper => is_alive(player),
+            ChatChannel::System => false,
+        }
+    }
+}
+
+fn is_werewolf(player: &PlayerData) -> bool {
+    player.role.as_deref() == Some(WEREWOLF_ROLE)
+}
+
+/// waiting players are alive as well, they just didn't get a role yet
+fn is_alive(player: &PlayerData) -> bool {
+    player.state == PlayerState::Waiting.as_str() || player.state == PlayerState::Alive.as_str()
+}
+
+/// history of all channels the player may read, or only of the channel if given.
+/// Returns at most limit messages in order, the newest ones before the
+/// message_id `before`, or the oldest after `after`, or the newest ones at all
+#[get("/<sid>/chat?<channel>&<before>&<after>&<limit>", format = "json")]
+fn get_chat(
+    sid: SessionID,
+    channel: Option<String>,
+    before: Option<i64>,
+    after: Option<i64>,
+    limit: Option<u32>,
+    auth: BasicAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<ChatMessage>>, ApiError> {
+    let member = ChatMember::from_auth(auth, &sid, &**db)?;
+
+    let channel = match channel {
+        Some(channel) => {
+            Some(ChatChannel::try_from(channel.as_str()).map_err(ApiError::bad_request)?)
+        }
+        None => None,
+    };
+    if let Some(channel) = channel {
+        if !member.can_read(channel) {
+            return Err(ApiError::forbidden("Not allowed to read this channel"));
+        }
+    }
+
+    // hidden messages are excluded by the query itself, so pages stay full
+    let query = ChatQuery {
+        before,
+        after,
+        channels: match channel {
+            Some(channel) => Some(vec![channel]),
+            None => member.readable_channels(),
+        },
+        whispers_of: member.user_id(),
+        limit: Some(limit.unwrap_or(DEFAULT_PAGE_LEN).clamp(1, MAX_PAGE_LEN)),
+    };
+
+    Ok(Json(db.get_chat_messages(&sid, &query)?))
+}
+
+#[post("/<sid>/chat", data = "<post_data>")]
+fn post_chat(
+    sid: SessionID,
+    auth: BasicAuthToken,
+    post_data: Json<ChatPostData>,
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+    moderation: State<Moderation>,
+) -> Result<Json<ChatMessage>, ApiError> {
+    let member = ChatMember::from_auth(auth, &sid, &**db)?;
+    let post_data = post_data.into_inner();
+    let channel = match post_data.to {
+        Some(_) => ChatChannel::Whisper,
+        None => post_data.channel.unwrap_or(ChatChannel::Village),
+    };
+    if channel == ChatChannel::Whisper && post_data.to.is_none() {
+        return Err(ApiError::bad_request("Whisper without recipient"));
+    }
+
+    let message = post_data.message.trim();
+    if message.is_empty() {
+        return Err(ApiError::bad_request("Empty message"));
+    }
+    if message.chars().count() > MAX_MESSAGE_LEN {
+        return Err(ApiError::bad_request(format!(
+            "Message longer than {} chars",
+            MAX_MESSAGE_LEN
+        )));
+    }
+
+    let sd = db.get_session_data(&sid)?;
+    let (phase, settings) = (sd.phase, sd.settings());
+    if !member.can_post(channel, phase) {
+        return Err(ApiError::forbidden(format!(
+            "Not allowed to post in {} during {}",
+            channel.as_str(),
+            phase.as_str()
+        )));
+    }
+
+    let recipient = match post_data.to {
+        Some(to) => Some(check_whisper(&member, to, &settings, &**db, &sid)?),
+        None => None,
+    };
+
+    // last, so refused messages don't count against the rate limit.
+    // Admins are neither muted nor limited or filtered
+    let message = match member.user_id() {
+        Some(user_id) => moderation
+            .check_post(sid, user_id, message)
+            .map_err(rejection_status)?,
+        None => message.to_owned(),
+    };
+
+    let msg = db
+        .add_chat_message(
+            &sid,
+            member.user_id(),
+            channel,
+            post_data.to,
+            &escape_html(&message),
+        )
+        .map_err(|e| {
+            warn!("Failed to store chat message: {}", e);
+            e
+        })?;
+
+    // the same rules as for the history decide who gets the message pushed
+    let readers = db
+        .get_players(&sid)?
+        .into_iter()
+        .map(ChatMember::Player)
+        .filter(|member| member.can_read_message(&msg))
+        .filter_map(|member| member.user_id())
+        .collect();
+
+    info!("New chat message {} in {}", msg.message_id, sid);
+    notifier.send(Notification::ChatMessage(sid, msg.clone(), readers));
+
+    if let (ChatMember::Player(sender), Some(recipient), true) =
+        (&member, recipient, settings.announce_whispers)
+    {
+        post_system_message(
+            &**db,
+            &sid,
+            &format!("{} flüstert {} etwas zu", sender.name, recipient.name),
+            &notifier,
+        );
+    }
+
+    Ok(Json(msg))
+}
+
+/// Ok with the recipient if the member may whisper to them
+fn check_whisper(
+    member: &ChatMember,
+    to: u32,
+    settings: &SessionSettings,
+    db: &dyn Storage,
+    sid: &SessionID,
+) -> Result<PlayerData, ApiError> {
+    if !settings.whispers_enabled {
+        return Err(ApiError::forbidden("Whispers are disabled in this session"));
+    }
+    if member.user_id().is_none() {
+        return Err(ApiError::forbidden("Only players can whisper"));
+    }
+    if member.user_id() == Some(to) {
+        return Err(ApiError::bad_request("Can't whisper to yourself"));
+    }
+
+    match db.get_player(sid, to) {
+        Ok(recipient) if is_alive(&recipient) => Ok(recipient),
+        Ok(_) => Err(ApiError::forbidden("Recipient is not alive")),
+        Err(DbError::NotFound(_)) => Err(ApiError::not_found("Recipient not found")),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// stores and pushes a message of the server to everyone in the session,
+/// so the transcript shows phase changes, deaths and whispers in order
+pub fn post_system_message(db: &dyn Storage, sid: &SessionID, text: &str, notifier: &Notifier) {
+    let posted = db
+        .add_chat_message(sid, None, ChatChannel::System, None, &escape_html(text))
+        .and_then(|msg| Ok((msg, db.get_players(sid)?)));
+    match posted {
+        Ok((msg, players)) => {
+            let readers = players.into_iter().map(|player| player.user_id).collect();
+            notifier.send(Notification::ChatMessage(*sid, msg, readers));
+        }
+        Err(e) => warn!("Failed to post system message in {}: {}", sid, e),
+    }
+}
+
+fn rejection_status(rejection: Rejection) -> ApiError {
+    match rejection {
+        Rejection::Muted { remaining, reason } => {
+            let remaining_secs = remaining.as_secs() + 1;
+            let message = match &reason {
+                Some(reason) => {
+                    format!("You are muted for another {}s: {}", remaining_secs, reason)
+                }
+                None => format!("You are muted for another {}s", remaining_secs),
+            };
+            ApiError::forbidden(message)
+                .with_code("muted")
+                .with_details(serde_json::json!({
+                    "remaining_secs": remaining_secs,
+                    "reason": reason,
+                }))
+        }
+        Rejection::RateLimited => {
+            ApiError::new(Status::TooManyRequests, "Too many messages, slow down")
+                .with_code("rate_limited")
+        }
+        Rejection::BlockedWords => ApiError::new(
+            Status::UnprocessableEntity,
+            "Message contains blocked words",
+        )
+        .with_code("blocked_words"),
+    }
+}
+
+#[get("/<sid>/chat/mutes", format = "json")]
+fn get_mutes(
+    sid: SessionID,
+    _auth: AdminAuthToken,
+    moderation: State<Moderation>,
+) -> Json<Vec<MuteInfo>> {
+    Json(moderation.mutes(sid))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct MuteData {
+    duration_secs: u64,
+    reason: Option<String>,
+}
+
+#[put("/<sid>/chat/mutes/<user_id>", data = "<mute_data>")]
+fn mute_player(
+    sid: SessionID,
+    user_id: u32,
+    _auth: AdminAuthToken,
+    mute_data: Json<MuteData>,
+    db: State<Arc<dyn Storage>>,
+    moderation: State<Moderation>,
+) -> Result<(), ApiError> {
+    db.get_player(&sid, user_id)?;
+    let mute_data = mute_data.into_inner();
+    moderation
+        .mute(
+            sid,
+            user_id,
+            Duration::from_secs(mute_data.duration_secs),
+            mute_data.reason,
+        )
+        .map_err(ApiError::bad_request)
+}
+
+#[delete("/<sid>/chat/mutes/<user_id>")]
+fn unmute_player(
+    sid: SessionID,
+    user_id: u32,
+    _auth: AdminAuthToken,
+    moderation: State<Moderation>,
+) -> Result<(), ApiError> {
+    if moderation.unmute(sid, user_id) {
+        Ok(())
+    } else {
+        Err(ApiError::not_found("Player isn't muted"))
+    }
+}
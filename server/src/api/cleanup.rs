@@ -0,0 +1,40 @@
+use crate::api::auth::AdminAuthToken;
+use crate::api::error::ApiError;
+use crate::api::net_types::CleanupReport;
+use crate::database::Storage;
+use crate::notify::Notifier;
+use crate::retention::Retention;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use std::sync::Arc;
+
+// mounted to /api/v1/cleanup/
+pub fn get_cleanup_api_routes() -> Vec<Route> {
+    routes![get_last_cleanup, run_cleanup]
+}
+
+/// admin only: what the last cleanup removed, 404 if it didn't run yet
+#[get("/", format = "json")]
+fn get_last_cleanup(
+    _auth: AdminAuthToken,
+    retention: State<Arc<Retention>>,
+) -> Result<Json<CleanupReport>, ApiError> {
+    retention
+        .last_report()
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found("No cleanup ran yet"))
+}
+
+/// admin only: cleans up now, dry_run defaults to the configured mode
+#[post("/?<dry_run>")]
+fn run_cleanup(
+    dry_run: Option<bool>,
+    _auth: AdminAuthToken,
+    retention: State<Arc<Retention>>,
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+) -> Result<Json<CleanupReport>, ApiError> {
+    let dry_run = dry_run.unwrap_or_else(|| retention.dry_run());
+    let report = retention.run(&**db, &notifier, dry_run)?;
+    Ok(Json(report))
+}
@@ -0,0 +1,175 @@
+use crate::api::net_types::ErrorBody;
+use crate::backup::BackupError;
+use crate::database::DbError;
+use log::error;
+use rocket::http::Status;
+use rocket::response::{self, content, Responder, Response};
+use rocket::{Catcher, Request};
+use rocket_contrib::json::Json;
+
+/// everything below answers errors as ErrorBody, pages keep html
+pub const API_BASE: &str = "/api/";
+
+/// the error of every api route, responds with the status and an ErrorBody
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub body: ErrorBody,
+}
+
+impl ApiError {
+    /// the code is derived from the status, see with_code for more specific ones
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            body: ErrorBody {
+                code: default_code(status).to_owned(),
+                message: message.into(),
+                details: None,
+            },
+        }
+    }
+
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.body.code = code.to_owned();
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.body.details = Some(details);
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        ApiError::new(Status::BadRequest, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        ApiError::new(Status::Forbidden, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        ApiError::new(Status::NotFound, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        ApiError::new(Status::InternalServerError, message)
+    }
+}
+
+fn default_code(status: Status) -> &'static str {
+    match status.code {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        409 => "conflict",
+        422 => "unprocessable_entity",
+        429 => "too_many_requests",
+        501 => "not_implemented",
+        503 => "unavailable",
+        _ => "internal_error",
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        Response::build_from(Json(self.body).respond_to(req)?)
+            .status(self.status)
+            .ok()
+    }
+}
+
+/// lets routes use ? on storage calls. Details of internal failures only go to the log
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        match &e {
+            DbError::NotFound(_) => ApiError::not_found(e.to_string()),
+            DbError::Conflict(_) => ApiError::new(Status::Conflict, e.to_string()),
+            DbError::Constraint(_) => ApiError::new(Status::UnprocessableEntity, e.to_string())
+                .with_code("constraint_violation"),
+            DbError::Busy => {
+                ApiError::new(Status::ServiceUnavailable, e.to_string()).with_code("database_busy")
+            }
+            DbError::Corrupt(_) | DbError::Io(_) => {
+                error!(target: "database", "{}", e);
+                ApiError::internal("Internal database error")
+            }
+        }
+    }
+}
+
+impl From<BackupError> for ApiError {
+    fn from(e: BackupError) -> Self {
+        match e {
+            BackupError::NotFound(_) => ApiError::not_found(e.to_string()),
+            BackupError::Db(e) => e.into(),
+            BackupError::Io(msg) => {
+                error!(target: crate::backup::BACKUP_LOG_TARGET, "{}", msg);
+                ApiError::internal("Backup failed")
+            }
+        }
+    }
+}
+
+/// rocket only has global catchers, so they answer html outside of the api
+pub fn get_api_catchers() -> Vec<Catcher> {
+    catchers![
+        bad_request,
+        unauthorized,
+        forbidden,
+        not_found,
+        unprocessable_entity,
+        internal_error
+    ]
+}
+
+type Caught = Result<ApiError, content::Html<String>>;
+
+fn caught(req: &Request, status: Status, message: &str) -> Caught {
+    if req.uri().path().starts_with(API_BASE) {
+        Ok(ApiError::new(status, message))
+    } else {
+        Err(content::Html(format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"UTF-8\">\n<title>{} {}</title>\n\
+            </head>\n<body>\n<h1>{} {}</h1>\n<p>{}</p>\n</body>\n</html>\n",
+            status.code, status.reason, status.code, status.reason, message
+        )))
+    }
+}
+
+#[catch(400)]
+fn bad_request(req: &Request) -> Caught {
+    caught(req, Status::BadRequest, "The request is malformed")
+}
+
+/// the auth guards fail with this under the api, pages redirect to their login instead
+#[catch(401)]
+fn unauthorized(req: &Request) -> Caught {
+    caught(req, Status::Unauthorized, "Missing or invalid auth token")
+}
+
+#[catch(403)]
+fn forbidden(req: &Request) -> Caught {
+    caught(req, Status::Forbidden, "Not allowed with this auth token")
+}
+
+#[catch(404)]
+fn not_found(req: &Request) -> Caught {
+    caught(req, Status::NotFound, "Nothing found at this address")
+}
+
+/// json bodies that don't match the expected type
+#[catch(422)]
+fn unprocessable_entity(req: &Request) -> Caught {
+    caught(
+        req,
+        Status::UnprocessableEntity,
+        "The request body doesn't have the expected format",
+    )
+}
+
+#[catch(500)]
+fn internal_error(req: &Request) -> Caught {
+    caught(req, Status::InternalServerError, "Internal server error")
+}
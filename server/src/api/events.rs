@@ -0,0 +1,123 @@
+use crate::api::auth::{AdminAuthToken, SessionID};
+use crate::api::chat;
+use crate::api::error::ApiError;
+use crate::api::net_types::{GameEvent, GamePhase, GameState, LoggedEvent};
+use crate::database::Storage;
+use crate::game_log;
+use crate::notify::{Notification, Notifier};
+use log::info;
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use std::sync::Arc;
+
+// mounted to /api/v1/sessions/ next to the session routes
+pub fn get_event_api_routes() -> Vec<Route> {
+    routes![get_events, post_event, get_replay]
+}
+
+/// admin only: the whole game log of the session
+#[get("/<sid>/events", format = "json")]
+fn get_events(
+    sid: SessionID,
+    _auth: AdminAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<LoggedEvent>>, ApiError> {
+    db.get_session_data(&sid)?;
+    Ok(Json(db.get_events(&sid)?))
+}
+
+/// admins record votes, night actions, roles and deaths
+#[post("/<sid>/events", data = "<event>")]
+fn post_event(
+    sid: SessionID,
+    _auth: AdminAuthToken,
+    event: Json<GameEvent>,
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+) -> Result<Json<LoggedEvent>, ApiError> {
+    let logged = apply(&**db, &sid, event.into_inner(), &notifier)?;
+    Ok(Json(logged))
+}
+
+/// admin only: the state rebuilt from the log, up to and including event seq `until` if set
+#[get("/<sid>/replay?<until>", format = "json")]
+fn get_replay(
+    sid: SessionID,
+    until: Option<i64>,
+    _auth: AdminAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<GameState>, ApiError> {
+    db.get_session_data(&sid)?;
+    let events = db.get_events(&sid)?;
+    Ok(Json(game_log::replay(events.iter().filter(|logged| {
+        until.map(|seq| logged.seq <= seq).unwrap_or(true)
+    }))))
+}
+
+/// stores the event, announces it in the chat and tells the clients what changed
+pub fn apply(
+    db: &dyn Storage,
+    sid: &SessionID,
+    event: GameEvent,
+    notifier: &Notifier,
+) -> Result<LoggedEvent, ApiError> {
+    let logged = db.apply_event(sid, event)?;
+    info!("{} event {}: {}", sid, logged.seq, logged.event.kind());
+
+    match &logged.event {
+        GameEvent::PhaseChanged { phase } => {
+            let announcement = match phase {
+                GamePhase::Lobby => "Zurück in der Lobby",
+                GamePhase::Day => "Es wird Tag",
+                GamePhase::Night => "Es wird Nacht",
+                GamePhase::Ended => "Das Spiel ist vorbei",
+            };
+            chat::post_system_message(db, sid, announcement, notifier);
+        }
+        GameEvent::Won { winner } => {
+            chat::post_system_message(
+                db,
+                sid,
+                &format!("Das Spiel ist vorbei, {} gewinnt", winner),
+                notifier,
+            );
+        }
+        GameEvent::Died { user_id, .. } => {
+            if let Ok(player) = db.get_player(sid, *user_id) {
+                chat::post_system_message(
+                    db,
+                    sid,
+                    &format!("{} ist gestorben", player.name),
+                    notifier,
+                );
+            }
+        }
+        _ => {}
+    }
+
+    match &logged.event {
+        GameEvent::PhaseChanged { .. } | GameEvent::Won { .. } => {
+            notifier.send(Notification::CustomToSession(
+                *sid,
+                "update.phase".to_owned(),
+            ));
+            notifier.send(Notification::UpdateSessionList);
+        }
+        GameEvent::RoleAssigned { .. }
+        | GameEvent::Died { .. }
+        | GameEvent::StateChanged { .. } => {
+            notifier.send(Notification::UpdatePlayerList(*sid));
+        }
+        _ => {}
+    }
+
+    if let GameEvent::PhaseChanged {
+        phase: GamePhase::Ended,
+    }
+    | GameEvent::Won { .. } = logged.event
+    {
+        notifier.send(Notification::SessionClosed(*sid));
+    }
+
+    Ok(logged)
+}
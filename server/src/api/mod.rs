@@ -1,57 +1,80 @@
-use crate::api::net_types::Stats;
-use crate::database::Database;
-use crate::notify::{Notification, Notifier};
-use log::error;
-use log::info;
-use rocket::http::uri::Origin;
+use crate::api::auth::AdminAuthToken;
+use crate::api::error::ApiError;
+use crate::api::net_types::{Stats, WsInfo};
+use crate::database::Storage;
+use crate::notify::Notifier;
 use rocket::{Rocket, Route, State};
 use rocket_contrib::json::Json;
-use serde::Serialize;
-use std::sync::atomic::Ordering;
-use std::sync::{atomic::AtomicI64, Arc};
-use std::time::Duration;
+use std::sync::Arc;
 
+pub mod archive;
 pub mod auth;
+pub mod backups;
+pub mod chat;
+pub mod cleanup;
+pub mod error;
+pub mod events;
 pub mod net_types;
+pub mod openapi;
 pub mod session;
+pub mod transcript;
+
+/// every api route with the base it gets mounted to, the openapi spec is built from this
+pub fn current_api_routes() -> Vec<(&'static str, Vec<Route>)> {
+    vec![
+        ("/api/v1/", routes![stats, ws_info, openapi::get_openapi]),
+        ("/api/v1/auth/", auth::get_auth_api_routes()),
+        ("/api/v1/sessions/", session::get_session_api_routes()),
+        ("/api/v1/sessions/", chat::get_chat_api_routes()),
+        ("/api/v1/sessions/", transcript::get_transcript_api_routes()),
+        ("/api/v1/sessions/", events::get_event_api_routes()),
+        ("/api/v1/games/", archive::get_archive_api_routes()),
+        ("/api/v1/cleanup/", cleanup::get_cleanup_api_routes()),
+        ("/api/v1/backups/", backups::get_backup_api_routes()),
+    ]
+}
 
 /// Gets api routes <...> so that /api/v1/<...> should get exposed
 /// So gets mounted to /api/v1
 pub fn mount_current_api_routes(mut rocket: Rocket) -> Rocket {
+    rocket = rocket.register(error::get_api_catchers());
+    for (base, routes) in current_api_routes() {
+        rocket = rocket.mount(base, routes);
+    }
     rocket
-        .mount("/api/v1/", routes![stats])
-        .mount("/api/v1/auth/", auth::get_auth_api_routes())
-        .mount("/api/v1/sessions/", session::get_session_api_routes())
 }
 
-#[get("/stats")]
-fn stats(notifier: State<Notifier>, db: State<Database>) -> Json<Stats> {
-    // get ws connected
-    let shared_ws_connected = Arc::new(AtomicI64::new(-1));
-    notifier.send(Notification::UpdateConnectionsAlive(Arc::clone(
-        &shared_ws_connected,
-    )));
-
-    let conn = db.get_locked_conn();
-    let sessions_active = Database::get_sessions_active(conn);
-    let mut ws_connected: i64 = -1;
-    for _ in 0..8 {
-        // try if was updated
-        ws_connected = shared_ws_connected.load(Ordering::Relaxed);
-        if ws_connected >= 0 {
-            break;
-        }
-        std::thread::sleep(Duration::from_millis(1));
-    }
+/// tells clients where to open the notification websocket
+#[get("/ws")]
+fn ws_info(notifier: State<Notifier>) -> Json<WsInfo> {
+    Json(WsInfo {
+        url: notifier.public_url().map(str::to_owned),
+        port: notifier.port(),
+    })
+}
 
-    if ws_connected < 0 {
-        ws_connected = 0;
-        error!("Notifier didn't respond");
-    }
+/// admin only: the notifier counts are read from its shared counters, the rest
+/// comes from one query
+#[get("/stats")]
+fn stats(
+    _auth: AdminAuthToken,
+    notifier: State<Notifier>,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<Stats>, ApiError> {
+    let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let counts = db.get_stats(now.saturating_sub(60))?;
 
-    Json(Stats {
-        ws_connected: ws_connected as u32,
-        sessions_active,
-        unique_users: 0,
-    })
+    Ok(Json(Stats {
+        ws_connected: notifier.connections(),
+        unique_users: counts.unique_users,
+        players_online: notifier.players_online(),
+        sessions_active: counts.sessions_active,
+        players_per_session: if counts.sessions_active == 0 {
+            0.0
+        } else {
+            counts.players_active as f32 / counts.sessions_active as f32
+        },
+        games_in_progress: counts.games_in_progress,
+        messages_per_minute: counts.messages_since,
+    }))
 }
@@ -1,10 +1,34 @@
-use serde::Serialize;
+use crate::api::auth::player_token::PlayerState;
+use crate::api::auth::SessionID;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
+/// admin only, numbers of the running server
 #[derive(Serialize)]
 pub struct Stats {
+    /// open websockets of players and controllers
     pub ws_connected: u32,
+    /// distinct players stored in any session, active or not
+    pub unique_users: u32,
+    /// players with at least one open websocket
+    pub players_online: u32,
     pub sessions_active: u32,
-    pub unique_users: u64,
+    /// average of the active sessions, 0 without any
+    pub players_per_session: f32,
+    /// active sessions at day or night
+    pub games_in_progress: u32,
+    /// chat messages of the last minute
+    pub messages_per_minute: u32,
+}
+
+/// where clients connect to receive notifications
+#[derive(Serialize)]
+pub struct WsInfo {
+    /// None if the websocket is served on the host of the http server
+    pub url: Option<String>,
+    /// the websocket has its own port, not the one of the http server.
+    /// Only used if url is None
+    pub port: u16,
 }
 
 #[derive(Serialize)]
@@ -13,9 +37,72 @@ pub struct BasicSessionInfo {
     pub player_count: u32,
     pub active: bool,
     pub created: u64,
+    pub phase: GamePhase,
 }
 
+/// one page of the session list
 #[derive(Serialize)]
+pub struct SessionPage {
+    pub sessions: Vec<BasicSessionInfo>,
+    /// pass as cursor to get the next page, null on the last one
+    pub next_cursor: Option<String>,
+}
+
+/// stored as json in sessions.settings
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// if players may whisper to each other
+    pub whispers_enabled: bool,
+    /// post "A whispered to B" to the village, without the content
+    pub announce_whispers: bool,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            whispers_enabled: true,
+            announce_whispers: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GamePhase {
+    /// players are joining, the game hasn't started yet
+    Lobby,
+    Day,
+    Night,
+    Ended,
+}
+
+impl GamePhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GamePhase::Lobby => "lobby",
+            GamePhase::Day => "day",
+            GamePhase::Night => "night",
+            GamePhase::Ended => "ended",
+        }
+    }
+}
+
+impl TryFrom<&str> for GamePhase {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "lobby" => Ok(GamePhase::Lobby),
+            "day" => Ok(GamePhase::Day),
+            "night" => Ok(GamePhase::Night),
+            "ended" => Ok(GamePhase::Ended),
+            _ => Err("Unknown game phase"),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub struct PlayerData {
     pub user_id: u32,
     pub name: String,
@@ -23,4 +110,244 @@ pub struct PlayerData {
     pub role: Option<String>,
     pub joined: u64,
     pub state: String,
+    pub presence: Presence,
+}
+
+/// if a player has an open websocket connection right now
+#[derive(Serialize, Clone, Copy)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Presence {
+    Online,
+    /// last_seen is None if the player never connected or the session went idle since
+    Offline {
+        last_seen: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Clone)]
+pub struct ChatMessage {
+    pub message_id: i64,
+    /// user_id of the sending player, None if sent by an admin
+    pub sender: Option<u32>,
+    pub sender_name: String,
+    pub channel: ChatChannel,
+    /// recipient of a whisper
+    pub to: Option<u32>,
+    pub to_name: Option<String>,
+    /// html escaped
+    pub message: String,
+    pub send_date: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatChannel {
+    /// public, muted at night
+    Village,
+    /// private chat of the werewolves at night
+    Wolves,
+    /// dead players and spectators, hidden from the living
+    Dead,
+    /// between two players, only readable by them and admins
+    Whisper,
+    /// posted by the server on phase changes, deaths and whispers, readable by everyone
+    System,
+}
+
+impl ChatChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatChannel::Village => "village",
+            ChatChannel::Wolves => "wolves",
+            ChatChannel::Dead => "dead",
+            ChatChannel::Whisper => "whisper",
+            ChatChannel::System => "system",
+        }
+    }
+}
+
+impl TryFrom<&str> for ChatChannel {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "village" => Ok(ChatChannel::Village),
+            "wolves" => Ok(ChatChannel::Wolves),
+            "dead" => Ok(ChatChannel::Dead),
+            "whisper" => Ok(ChatChannel::Whisper),
+            "system" => Ok(ChatChannel::System),
+            _ => Err("Unknown chat channel"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct MuteInfo {
+    pub user_id: u32,
+    /// unix secs
+    pub until: u64,
+    pub reason: Option<String>,
+}
+
+/// an immutable entry of the game log, the tables only hold the latest state
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    Joined {
+        user_id: u32,
+        name: String,
+    },
+    RoleAssigned {
+        user_id: u32,
+        role: String,
+    },
+    PhaseChanged {
+        phase: GamePhase,
+    },
+    VoteCast {
+        voter: u32,
+        target: u32,
+    },
+    NightAction {
+        user_id: u32,
+        action: String,
+        target: Option<u32>,
+    },
+    Died {
+        user_id: u32,
+        cause: Option<String>,
+    },
+    /// revived or moved to the spectators
+    StateChanged {
+        user_id: u32,
+        state: PlayerState,
+    },
+    Won {
+        winner: String,
+    },
+}
+
+impl GameEvent {
+    /// stored next to the json, so the log can be filtered without parsing it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GameEvent::Joined { .. } => "joined",
+            GameEvent::RoleAssigned { .. } => "role_assigned",
+            GameEvent::PhaseChanged { .. } => "phase_changed",
+            GameEvent::VoteCast { .. } => "vote_cast",
+            GameEvent::NightAction { .. } => "night_action",
+            GameEvent::Died { .. } => "died",
+            GameEvent::StateChanged { .. } => "state_changed",
+            GameEvent::Won { .. } => "won",
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LoggedEvent {
+    /// increasing over all sessions, gives the order
+    pub seq: i64,
+    /// unix secs
+    pub created: u64,
+    pub event: GameEvent,
+}
+
+/// a session rebuilt from its events
+#[derive(Serialize, Debug)]
+pub struct GameState {
+    pub phase: GamePhase,
+    /// unix secs the current game left the lobby
+    pub started: Option<u64>,
+    /// number of nights so far
+    pub round: u32,
+    pub players: Vec<PlayerSnapshot>,
+    pub winner: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PlayerSnapshot {
+    pub user_id: u32,
+    pub name: String,
+    pub role: Option<String>,
+    pub state: PlayerState,
+    pub death_cause: Option<String>,
+    pub death_round: Option<u32>,
+}
+
+/// a finished game, kept after the session is gone
+#[derive(Serialize, Clone, Debug)]
+pub struct ArchivedGame {
+    pub game_id: i64,
+    pub session_id: String,
+    /// unix secs
+    pub started: u64,
+    pub ended: u64,
+    pub winner: Option<String>,
+    pub rounds: u32,
+    pub players: Vec<PlayerSnapshot>,
+}
+
+/// list entry of the archive, without the players
+#[derive(Serialize, Debug)]
+pub struct ArchivedGameInfo {
+    pub game_id: i64,
+    pub session_id: String,
+    pub started: u64,
+    pub ended: u64,
+    pub winner: Option<String>,
+    pub rounds: u32,
+    pub player_count: u32,
+}
+
+impl From<&ArchivedGame> for ArchivedGameInfo {
+    fn from(game: &ArchivedGame) -> Self {
+        ArchivedGameInfo {
+            game_id: game.game_id,
+            session_id: game.session_id.clone(),
+            started: game.started,
+            ended: game.ended,
+            winner: game.winner.clone(),
+            rounds: game.rounds,
+            player_count: game.players.len() as u32,
+        }
+    }
+}
+
+/// what a cleanup run removed, or would have removed in a dry run
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    /// unix secs
+    pub ran_at: u64,
+    pub sessions_deactivated: u32,
+    pub sessions_deleted: u32,
+    pub players_deleted: u32,
+    pub messages_deleted: u32,
+    pub events_deleted: u32,
+    /// the sessions behind sessions_deactivated and sessions_deleted, their clients get notified
+    #[serde(skip)]
+    pub deactivated: Vec<SessionID>,
+    #[serde(skip)]
+    pub deleted: Vec<SessionID>,
+}
+
+/// a database backup in the backup directory
+#[derive(Serialize, Debug)]
+pub struct BackupInfo {
+    pub name: String,
+    /// unix secs, also part of the name
+    pub created: u64,
+    /// bytes
+    pub size: u64,
+}
+
+/// the body of every error response of the api
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    /// stable and machine readable, e.g. not_found or muted
+    pub code: String,
+    /// for humans, may change
+    pub message: String,
+    /// more about the error depending on the code, null if there's nothing to add
+    pub details: Option<serde_json::Value>,
 }
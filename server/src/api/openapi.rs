@@ -0,0 +1,1330 @@
+use crate::api::current_api_routes;
+use log::warn;
+use rocket::http::Method;
+use rocket::Route;
+use rocket_contrib::json::Json;
+use serde_json::{json, Map, Value};
+
+/// who may call an operation, see the auth_level of the token
+enum Auth {
+    Public,
+    /// players of the session and admins
+    Any,
+    Admin,
+}
+
+/// what the body of a request or a successful response holds
+enum Body {
+    Empty,
+    /// an object of components/schemas
+    Json(&'static str),
+    /// a list of the objects
+    JsonList(&'static str),
+    /// any json, only the spec itself
+    AnyJson,
+    /// plain text, e.g. a jwt
+    Text,
+    /// json of the schema, or markdown or html depending on the format parameter
+    Export(&'static str),
+}
+
+/// documentation of one route, the path is the full one in rocket syntax
+struct Operation {
+    method: Method,
+    path: &'static str,
+    summary: &'static str,
+    auth: Auth,
+    request: Body,
+    response: Body,
+}
+
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/stats",
+        summary: "Connections, players online and activity of the running server",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("Stats"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/ws",
+        summary: "Where clients open the notification websocket",
+        auth: Auth::Public,
+        request: Body::Empty,
+        response: Body::Json("WsInfo"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/openapi.json",
+        summary: "This document",
+        auth: Auth::Public,
+        request: Body::Empty,
+        response: Body::AnyJson,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/auth/status",
+        summary: "Not implemented yet",
+        auth: Auth::Public,
+        request: Body::Empty,
+        response: Body::Text,
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/auth/connect/client",
+        summary: "Joins a session as a new player, responds with the player token",
+        auth: Auth::Public,
+        request: Body::Json("ConnectData"),
+        response: Body::Text,
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/auth/connect/ctrl",
+        summary: "Responds with an admin token",
+        auth: Auth::Public,
+        request: Body::Json("ConnectAdminData"),
+        response: Body::Text,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/",
+        summary: "One page of the sessions, sort is newest (default), oldest or players \
+                  and cursor the next_cursor of the previous page",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("SessionPage"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>",
+        summary: "One session",
+        auth: Auth::Any,
+        request: Body::Empty,
+        response: Body::Json("BasicSessionInfo"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/playerlist",
+        summary: "Players of the session with their presence",
+        auth: Auth::Any,
+        request: Body::Empty,
+        response: Body::JsonList("PlayerData"),
+    },
+    Operation {
+        method: Method::Put,
+        path: "/api/v1/sessions/<sid>/phase",
+        summary: "Moves the game to another phase",
+        auth: Auth::Admin,
+        request: Body::Json("PhaseData"),
+        response: Body::Empty,
+    },
+    Operation {
+        method: Method::Put,
+        path: "/api/v1/sessions/<sid>/players/<user_id>/state",
+        summary: "Kills, revives or moves a player to the spectators",
+        auth: Auth::Admin,
+        request: Body::Json("StateData"),
+        response: Body::Empty,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/settings",
+        summary: "Settings of the session",
+        auth: Auth::Any,
+        request: Body::Empty,
+        response: Body::Json("SessionSettings"),
+    },
+    Operation {
+        method: Method::Put,
+        path: "/api/v1/sessions/<sid>/settings",
+        summary: "Replaces the settings of the session",
+        auth: Auth::Admin,
+        request: Body::Json("SessionSettings"),
+        response: Body::Empty,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/chat",
+        summary: "Chat history readable by the caller, the newest messages or those between \
+                  before and after",
+        auth: Auth::Any,
+        request: Body::Empty,
+        response: Body::JsonList("ChatMessage"),
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/sessions/<sid>/chat",
+        summary: "Posts a message, setting to makes it a whisper",
+        auth: Auth::Any,
+        request: Body::Json("ChatPostData"),
+        response: Body::Json("ChatMessage"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/chat/mutes",
+        summary: "Muted players of the session",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::JsonList("MuteInfo"),
+    },
+    Operation {
+        method: Method::Put,
+        path: "/api/v1/sessions/<sid>/chat/mutes/<user_id>",
+        summary: "Mutes a player",
+        auth: Auth::Admin,
+        request: Body::Json("MuteData"),
+        response: Body::Empty,
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/api/v1/sessions/<sid>/chat/mutes/<user_id>",
+        summary: "Unmutes a player",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Empty,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/transcript",
+        summary: "The whole chat of an ended game, as json (default), markdown or html",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Export("Transcript"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/events",
+        summary: "The game log of the session",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::JsonList("LoggedEvent"),
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/sessions/<sid>/events",
+        summary: "Records a game event, players join through connect/client",
+        auth: Auth::Admin,
+        request: Body::Json("GameEvent"),
+        response: Body::Json("LoggedEvent"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/sessions/<sid>/replay",
+        summary: "The state rebuilt from the log, up to and including the event until",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("GameState"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/games/",
+        summary: "Finished games, newest first, only those of player if set",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::JsonList("ArchivedGameInfo"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/games/<game_id>",
+        summary: "A finished game with every participant",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("ArchivedGame"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/cleanup/",
+        summary: "What the last cleanup removed",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("CleanupReport"),
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/cleanup/",
+        summary: "Cleans up now, dry_run defaults to the configured mode",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("CleanupReport"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/api/v1/backups/",
+        summary: "Existing backups, newest first",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::JsonList("BackupInfo"),
+    },
+    Operation {
+        method: Method::Post,
+        path: "/api/v1/backups/",
+        summary: "Backs up the running database",
+        auth: Auth::Admin,
+        request: Body::Empty,
+        response: Body::Json("BackupInfo"),
+    },
+];
+
+/// OpenAPI 3 description of every route under /api/v1
+#[get("/openapi.json")]
+pub fn get_openapi() -> Json<Value> {
+    Json(spec())
+}
+
+/// path of the mounted route in rocket syntax, without the query
+fn full_path(base: &str, route: &Route) -> String {
+    match route.uri.path() {
+        "/" => base.to_owned(),
+        path => [base.trim_end_matches('/'), path].concat(),
+    }
+}
+
+fn find_operation(base: &str, route: &Route) -> Option<&'static Operation> {
+    let path = full_path(base, route);
+    OPERATIONS
+        .iter()
+        .find(|op| op.method == route.method && op.path == path)
+}
+
+/// name of a dynamic segment or query parameter like <sid> or <path..>
+fn param_name(segment: &str) -> Option<&str> {
+    if segment.starts_with('<') && segment.ends_with('>') {
+        Some(segment[1..segment.len() - 1].trim_end_matches(".."))
+    } else {
+        None
+    }
+}
+
+fn param_schema(name: &str) -> Value {
+    match name {
+        "user_id" | "game_id" | "created_after" | "before" | "after" | "until" | "limit" => {
+            integer()
+        }
+        "active" | "dry_run" => boolean(),
+        "format" => string_enum(&["json", "markdown", "html"]),
+        _ => string(),
+    }
+}
+
+fn parameters(route: &Route) -> Vec<Value> {
+    let in_path = route.uri.path().split('/').filter_map(param_name).map(
+        |name| json!({"name": name, "in": "path", "required": true, "schema": param_schema(name)}),
+    );
+    let in_query = route
+        .uri
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter_map(param_name)
+        .map(|name| {
+            json!({"name": name, "in": "query", "required": false, "schema": param_schema(name)})
+        });
+    in_path.chain(in_query).collect()
+}
+
+fn content(body: &Body) -> Option<Value> {
+    let (media_type, schema) = match body {
+        Body::Empty => return None,
+        Body::Json(name) => ("application/json", reference(name)),
+        Body::JsonList(name) => ("application/json", array(reference(name))),
+        Body::AnyJson => ("application/json", json!({"type": "object"})),
+        Body::Text => ("text/plain", string()),
+        Body::Export(name) => {
+            return Some(json!({
+                "application/json": {"schema": reference(name)},
+                "text/markdown": {"schema": string()},
+                "text/html": {"schema": string()},
+            }))
+        }
+    };
+    Some(json!({ media_type: {"schema": schema} }))
+}
+
+fn operation(op: &Operation, route: &Route) -> Value {
+    let mut success = json!({"description": "Success"});
+    if let Some(content) = content(&op.response) {
+        success["content"] = content;
+    }
+
+    let mut value = json!({
+        "summary": op.summary,
+        "parameters": parameters(route),
+        "responses": {
+            "200": success,
+            "default": {
+                "description": "Error",
+                "content": {"application/json": {"schema": reference("ErrorBody")}},
+            },
+        },
+    });
+    if let Some(content) = content(&op.request) {
+        value["requestBody"] = json!({"required": true, "content": content});
+    }
+    match op.auth {
+        Auth::Public => {}
+        Auth::Any => {
+            value["security"] = json!([{"bearer": []}]);
+            value["x-auth-level"] = json!("any");
+        }
+        Auth::Admin => {
+            value["security"] = json!([{"bearer": []}]);
+            value["x-auth-level"] = json!("control");
+        }
+    }
+    value
+}
+
+/// the document served at /api/v1/openapi.json. Routes without an entry in
+/// OPERATIONS are left out, the tests make sure there are none
+pub fn spec() -> Value {
+    let mut paths = Map::new();
+    for (base, routes) in current_api_routes() {
+        for route in &routes {
+            let op = match find_operation(base, route) {
+                Some(op) => op,
+                None => {
+                    warn!(
+                        "{} {} is missing in the openapi spec",
+                        route.method, route.uri
+                    );
+                    continue;
+                }
+            };
+            let path = full_path(base, route)
+                .split('/')
+                .map(|segment| match param_name(segment) {
+                    Some(name) => format!("{{{}}}", name),
+                    None => segment.to_owned(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+
+            let item = paths.entry(path).or_insert_with(|| json!({}));
+            item[route.method.as_str().to_lowercase()] = operation(op, route);
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "werwolf-online",
+            "version": "1",
+        },
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearer": {"type": "http", "scheme": "bearer", "bearerFormat": "JWT"},
+            },
+            "schemas": schemas(),
+        },
+    })
+}
+
+fn integer() -> Value {
+    json!({"type": "integer"})
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn boolean() -> Value {
+    json!({"type": "boolean"})
+}
+
+/// serialized as null for None
+fn nullable(mut schema: Value) -> Value {
+    schema["nullable"] = json!(true);
+    schema
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+fn string_enum(values: &[&str]) -> Value {
+    json!({"type": "string", "enum": values})
+}
+
+/// all fields are required, like serde serializes them
+fn object(fields: Vec<(&str, Value)>) -> Value {
+    let required: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+    let properties: Map<String, Value> = fields
+        .into_iter()
+        .map(|(name, schema)| (name.to_owned(), schema))
+        .collect();
+    json!({"type": "object", "required": required, "properties": properties})
+}
+
+/// a request body shown in the docs, the tests parse it into the rust type
+fn with_example(mut schema: Value, example: Value) -> Value {
+    schema["example"] = example;
+    schema
+}
+
+/// a variant of an enum tagged with tag, like #[serde(tag = "...")]
+fn tagged<'a>(tag: &'a str, variant: &str, mut fields: Vec<(&'a str, Value)>) -> Value {
+    fields.insert(0, (tag, string_enum(&[variant])));
+    object(fields)
+}
+
+/// the net_types and request bodies, named like the structs
+fn schemas() -> Value {
+    let player_state = || reference("PlayerState");
+    json!({
+        "Stats": object(vec![
+            ("ws_connected", integer()),
+            ("unique_users", integer()),
+            ("players_online", integer()),
+            ("sessions_active", integer()),
+            ("players_per_session", json!({"type": "number"})),
+            ("games_in_progress", integer()),
+            ("messages_per_minute", integer()),
+        ]),
+        "WsInfo": object(vec![
+            ("url", nullable(string())),
+            ("port", integer()),
+        ]),
+        "BasicSessionInfo": object(vec![
+            ("id", string()),
+            ("player_count", integer()),
+            ("active", boolean()),
+            ("created", integer()),
+            ("phase", reference("GamePhase")),
+        ]),
+        "SessionPage": object(vec![
+            ("sessions", array(reference("BasicSessionInfo"))),
+            ("next_cursor", nullable(string())),
+        ]),
+        "SessionSettings": with_example(
+            object(vec![
+                ("whispers_enabled", boolean()),
+                ("announce_whispers", boolean()),
+            ]),
+            json!({"whispers_enabled": true, "announce_whispers": false}),
+        ),
+        "GamePhase": string_enum(&["lobby", "day", "night", "ended"]),
+        "PlayerState": string_enum(&["waiting", "alive", "dead", "spectator"]),
+        "ChatChannel": string_enum(&["village", "wolves", "dead", "whisper", "system"]),
+        "PlayerData": object(vec![
+            ("user_id", integer()),
+            ("name", string()),
+            ("role", nullable(string())),
+            ("joined", integer()),
+            ("state", string()),
+            ("presence", reference("Presence")),
+        ]),
+        "Presence": {
+            "oneOf": [
+                tagged("status", "online", vec![]),
+                tagged("status", "offline", vec![("last_seen", nullable(integer()))]),
+            ],
+        },
+        "ChatMessage": object(vec![
+            ("message_id", integer()),
+            ("sender", nullable(integer())),
+            ("sender_name", string()),
+            ("channel", reference("ChatChannel")),
+            ("to", nullable(integer())),
+            ("to_name", nullable(string())),
+            ("message", string()),
+            ("send_date", integer()),
+        ]),
+        "MuteInfo": object(vec![
+            ("user_id", integer()),
+            ("until", integer()),
+            ("reason", nullable(string())),
+        ]),
+        "GameEvent": {
+            "example": {"type": "phase_changed", "phase": "night"},
+            "oneOf": [
+                tagged("type", "joined", vec![("user_id", integer()), ("name", string())]),
+                tagged("type", "role_assigned", vec![("user_id", integer()), ("role", string())]),
+                tagged("type", "phase_changed", vec![("phase", reference("GamePhase"))]),
+                tagged("type", "vote_cast", vec![("voter", integer()), ("target", integer())]),
+                tagged("type", "night_action", vec![
+                    ("user_id", integer()),
+                    ("action", string()),
+                    ("target", nullable(integer())),
+                ]),
+                tagged("type", "died", vec![("user_id", integer()), ("cause", nullable(string()))]),
+                tagged("type", "state_changed", vec![
+                    ("user_id", integer()),
+                    ("state", player_state()),
+                ]),
+                tagged("type", "won", vec![("winner", string())]),
+            ],
+        },
+        "LoggedEvent": object(vec![
+            ("seq", integer()),
+            ("created", integer()),
+            ("event", reference("GameEvent")),
+        ]),
+        "GameState": object(vec![
+            ("phase", reference("GamePhase")),
+            ("started", nullable(integer())),
+            ("round", integer()),
+            ("players", array(reference("PlayerSnapshot"))),
+            ("winner", nullable(string())),
+        ]),
+        "PlayerSnapshot": object(vec![
+            ("user_id", integer()),
+            ("name", string()),
+            ("role", nullable(string())),
+            ("state", player_state()),
+            ("death_cause", nullable(string())),
+            ("death_round", nullable(integer())),
+        ]),
+        "ArchivedGame": object(vec![
+            ("game_id", integer()),
+            ("session_id", string()),
+            ("started", integer()),
+            ("ended", integer()),
+            ("winner", nullable(string())),
+            ("rounds", integer()),
+            ("players", array(reference("PlayerSnapshot"))),
+        ]),
+        "ArchivedGameInfo": object(vec![
+            ("game_id", integer()),
+            ("session_id", string()),
+            ("started", integer()),
+            ("ended", integer()),
+            ("winner", nullable(string())),
+            ("rounds", integer()),
+            ("player_count", integer()),
+        ]),
+        "CleanupReport": object(vec![
+            ("dry_run", boolean()),
+            ("ran_at", integer()),
+            ("sessions_deactivated", integer()),
+            ("sessions_deleted", integer()),
+            ("players_deleted", integer()),
+            ("messages_deleted", integer()),
+            ("events_deleted", integer()),
+        ]),
+        "BackupInfo": object(vec![
+            ("name", string()),
+            ("created", integer()),
+            ("size", integer()),
+        ]),
+        "Transcript": object(vec![
+            ("session_id", string()),
+            ("created", integer()),
+            ("players", array(reference("TranscriptPlayer"))),
+            ("messages", array(reference("ChatMessage"))),
+        ]),
+        "TranscriptPlayer": object(vec![
+            ("user_id", integer()),
+            ("name", string()),
+            ("role", nullable(string())),
+            ("joined", integer()),
+            ("state", string()),
+        ]),
+        "ErrorBody": object(vec![
+            ("code", string()),
+            ("message", string()),
+            ("details", nullable(json!({"type": "object"}))),
+        ]),
+        "ConnectData": with_example(
+            object(vec![("username", string()), ("session_id", string())]),
+            json!({"username": "Alice", "session_id": "AB12CD34"}),
+        ),
+        "ConnectAdminData": with_example(
+            object(vec![("password", string())]),
+            json!({"password": "secret"}),
+        ),
+        "ChatPostData": {
+            "type": "object",
+            "required": ["message"],
+            "properties": {
+                "message": string(),
+                "channel": reference("ChatChannel"),
+                "to": integer(),
+            },
+            "example": {"message": "psst", "channel": "whisper", "to": 2},
+        },
+        "PhaseData": with_example(
+            object(vec![("phase", reference("GamePhase"))]),
+            json!({"phase": "night"}),
+        ),
+        "StateData": with_example(
+            object(vec![("state", player_state())]),
+            json!({"state": "dead"}),
+        ),
+        "MuteData": {
+            "type": "object",
+            "required": ["duration_secs"],
+            "properties": {
+                "duration_secs": integer(),
+                "reason": string(),
+            },
+            "example": {"duration_secs": 600, "reason": "spam"},
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::player_token::PlayerState;
+    use crate::api::auth::SessionID;
+    use crate::api::net_types::*;
+    use crate::api::transcript::{Transcript, TranscriptPlayer};
+    use crate::api::{auth, chat, mount_current_api_routes, session};
+    use crate::backup::BackupConfig;
+    use crate::database::{MemoryStorage, SessionCursor, SessionSort, Storage};
+    use crate::moderation::{Moderation, ModerationConfig};
+    use crate::notify::{self, NotifierConfig};
+    use crate::retention::{Retention, RetentionConfig};
+    use crate::SessionData;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::Client;
+    use serde::de::DeserializeOwned;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    #[test]
+    fn every_route_is_in_the_spec() {
+        let spec = spec();
+        for (base, routes) in current_api_routes() {
+            for route in &routes {
+                assert!(
+                    find_operation(base, route).is_some(),
+                    "{} {}{} has no entry in OPERATIONS",
+                    route.method,
+                    base,
+                    route.uri
+                );
+            }
+        }
+
+        let documented: usize = spec["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|item| item.as_object().unwrap().len())
+            .sum();
+        assert_eq!(
+            documented,
+            OPERATIONS.len(),
+            "an entry of OPERATIONS has no route"
+        );
+    }
+
+    /// checks a value like a strict client would, undocumented fields are errors
+    fn validate(spec: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if value.is_null() {
+            return match schema["nullable"].as_bool() {
+                Some(true) => Ok(()),
+                _ => Err(format!("{} is null but not nullable", path)),
+            };
+        }
+        if let Some(target) = schema["$ref"].as_str() {
+            let name = target.trim_start_matches("#/components/schemas/");
+            return validate(spec, &spec["components"]["schemas"][name], value, path);
+        }
+        if let Some(variants) = schema["oneOf"].as_array() {
+            let matching = variants
+                .iter()
+                .filter(|variant| validate(spec, variant, value, path).is_ok())
+                .count();
+            return match matching {
+                1 => Ok(()),
+                n => Err(format!("{} matches {} variants of oneOf", path, n)),
+            };
+        }
+        if let Some(values) = schema["enum"].as_array() {
+            if !values.contains(value) {
+                return Err(format!("{} = {} is not in the enum", path, value));
+            }
+        }
+        let type_ok = match schema["type"].as_str() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("number") => value.is_number(),
+            Some("boolean") => value.is_boolean(),
+            other => return Err(format!("{} has an unknown schema type {:?}", path, other)),
+        };
+        if !type_ok {
+            return Err(format!(
+                "{} = {} is not of type {}",
+                path, value, schema["type"]
+            ));
+        }
+        if let Some(items) = value.as_array() {
+            for (i, item) in items.iter().enumerate() {
+                validate(spec, &schema["items"], item, &format!("{}[{}]", path, i))?;
+            }
+        }
+        if let (Some(fields), Some(properties)) =
+            (value.as_object(), schema["properties"].as_object())
+        {
+            for name in schema["required"].as_array().into_iter().flatten() {
+                let name = name.as_str().unwrap();
+                if !fields.contains_key(name) {
+                    return Err(format!("{}.{} is required but missing", path, name));
+                }
+            }
+            for (name, field) in fields {
+                match properties.get(name) {
+                    Some(property) => {
+                        validate(spec, property, field, &format!("{}.{}", path, name))?
+                    }
+                    None => return Err(format!("{}.{} is not documented", path, name)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn session_data() -> SessionData {
+        SessionData {
+            id: SessionID::try_from("AB12CD34").unwrap(),
+            created: std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000),
+            active: true,
+            settings: None,
+            phase: GamePhase::Day,
+            player_count: 4,
+        }
+    }
+
+    fn player_data(presence: Presence) -> PlayerData {
+        PlayerData {
+            user_id: 1,
+            name: "Alice".to_owned(),
+            role: Some("seer".to_owned()),
+            joined: 1_600_000_000,
+            state: "alive".to_owned(),
+            presence,
+        }
+    }
+
+    fn chat_message(sender: Option<u32>, channel: ChatChannel, to: Option<u32>) -> ChatMessage {
+        ChatMessage {
+            message_id: 7,
+            sender,
+            sender_name: "Alice".to_owned(),
+            channel,
+            to,
+            to_name: to.map(|_| "Bob".to_owned()),
+            message: "hello &amp; bye".to_owned(),
+            send_date: 1_600_000_100,
+        }
+    }
+
+    fn player_snapshot(death_cause: Option<&str>) -> PlayerSnapshot {
+        PlayerSnapshot {
+            user_id: 2,
+            name: "Bob".to_owned(),
+            role: None,
+            state: if death_cause.is_some() {
+                PlayerState::Dead
+            } else {
+                PlayerState::Alive
+            },
+            death_cause: death_cause.map(str::to_owned),
+            death_round: death_cause.map(|_| 2),
+        }
+    }
+
+    fn archived_game() -> ArchivedGame {
+        ArchivedGame {
+            game_id: 3,
+            session_id: "abc".to_owned(),
+            started: 1_600_000_000,
+            ended: 1_600_003_600,
+            winner: Some("village".to_owned()),
+            rounds: 4,
+            players: vec![player_snapshot(None), player_snapshot(Some("wolves"))],
+        }
+    }
+
+    fn game_events() -> Vec<GameEvent> {
+        vec![
+            GameEvent::Joined {
+                user_id: 1,
+                name: "Alice".to_owned(),
+            },
+            GameEvent::RoleAssigned {
+                user_id: 1,
+                role: "seer".to_owned(),
+            },
+            GameEvent::PhaseChanged {
+                phase: GamePhase::Night,
+            },
+            GameEvent::VoteCast {
+                voter: 1,
+                target: 2,
+            },
+            GameEvent::NightAction {
+                user_id: 1,
+                action: "see".to_owned(),
+                target: Some(2),
+            },
+            GameEvent::NightAction {
+                user_id: 1,
+                action: "sleep".to_owned(),
+                target: None,
+            },
+            GameEvent::Died {
+                user_id: 2,
+                cause: Some("wolves".to_owned()),
+            },
+            GameEvent::Died {
+                user_id: 2,
+                cause: None,
+            },
+            GameEvent::StateChanged {
+                user_id: 2,
+                state: PlayerState::Spectator,
+            },
+            GameEvent::Won {
+                winner: "village".to_owned(),
+            },
+        ]
+    }
+
+    /// one or more serialized instances of every net type, by schema name
+    fn samples() -> Vec<(&'static str, Value)> {
+        fn to_value<T: serde::Serialize>(value: T) -> Value {
+            serde_json::to_value(value).unwrap()
+        }
+
+        let mut samples = vec![
+            (
+                "Stats",
+                to_value(Stats {
+                    ws_connected: 3,
+                    unique_users: 4,
+                    players_online: 2,
+                    sessions_active: 1,
+                    players_per_session: 2.5,
+                    games_in_progress: 1,
+                    messages_per_minute: 12,
+                }),
+            ),
+            (
+                "WsInfo",
+                to_value(WsInfo {
+                    url: Some("wss://example.com/ws".to_owned()),
+                    port: 8001,
+                }),
+            ),
+            (
+                "WsInfo",
+                to_value(WsInfo {
+                    url: None,
+                    port: 8001,
+                }),
+            ),
+            (
+                "SessionPage",
+                to_value(SessionPage {
+                    sessions: vec![BasicSessionInfo::from(session_data())],
+                    next_cursor: Some(
+                        SessionCursor::of(SessionSort::Newest, &session_data()).to_string(),
+                    ),
+                }),
+            ),
+            (
+                "SessionPage",
+                to_value(SessionPage {
+                    sessions: vec![],
+                    next_cursor: None,
+                }),
+            ),
+            ("SessionSettings", to_value(SessionSettings::default())),
+            ("PlayerData", to_value(player_data(Presence::Online))),
+            (
+                "PlayerData",
+                to_value(player_data(Presence::Offline {
+                    last_seen: Some(1_600_000_050),
+                })),
+            ),
+            (
+                "PlayerData",
+                to_value(player_data(Presence::Offline { last_seen: None })),
+            ),
+            (
+                "ChatMessage",
+                to_value(chat_message(Some(1), ChatChannel::Whisper, Some(2))),
+            ),
+            (
+                "ChatMessage",
+                to_value(chat_message(None, ChatChannel::System, None)),
+            ),
+            (
+                "MuteInfo",
+                to_value(MuteInfo {
+                    user_id: 2,
+                    until: 1_600_000_600,
+                    reason: Some("spam".to_owned()),
+                }),
+            ),
+            (
+                "MuteInfo",
+                to_value(MuteInfo {
+                    user_id: 2,
+                    until: 1_600_000_600,
+                    reason: None,
+                }),
+            ),
+            (
+                "GameState",
+                to_value(GameState {
+                    phase: GamePhase::Ended,
+                    started: Some(1_600_000_000),
+                    round: 4,
+                    players: vec![player_snapshot(None), player_snapshot(Some("vote"))],
+                    winner: Some("wolves".to_owned()),
+                }),
+            ),
+            (
+                "GameState",
+                to_value(GameState {
+                    phase: GamePhase::Lobby,
+                    started: None,
+                    round: 0,
+                    players: vec![],
+                    winner: None,
+                }),
+            ),
+            ("ArchivedGame", to_value(archived_game())),
+            (
+                "ArchivedGameInfo",
+                to_value(ArchivedGameInfo::from(&archived_game())),
+            ),
+            (
+                "CleanupReport",
+                to_value(CleanupReport {
+                    dry_run: true,
+                    ran_at: 1_600_000_000,
+                    sessions_deactivated: 1,
+                    sessions_deleted: 2,
+                    players_deleted: 3,
+                    messages_deleted: 4,
+                    events_deleted: 5,
+                    // not part of the response, only for the notifications
+                    deactivated: vec![SessionID::try_from("AAAAAAAA").unwrap()],
+                    deleted: Vec::new(),
+                }),
+            ),
+            (
+                "BackupInfo",
+                to_value(BackupInfo {
+                    name: "backup-1600000000.sqlite".to_owned(),
+                    created: 1_600_000_000,
+                    size: 4096,
+                }),
+            ),
+            (
+                "Transcript",
+                to_value(Transcript {
+                    session_id: "abc".to_owned(),
+                    created: 1_600_000_000,
+                    players: vec![TranscriptPlayer::from(player_data(Presence::Online))],
+                    messages: vec![chat_message(Some(1), ChatChannel::Village, None)],
+                }),
+            ),
+            (
+                "ErrorBody",
+                to_value(ErrorBody {
+                    code: "muted".to_owned(),
+                    message: "You are muted".to_owned(),
+                    details: Some(json!({"until": 1_600_000_600})),
+                }),
+            ),
+            (
+                "ErrorBody",
+                to_value(ErrorBody {
+                    code: "not_found".to_owned(),
+                    message: "Unknown session".to_owned(),
+                    details: None,
+                }),
+            ),
+        ];
+        for (seq, event) in game_events().into_iter().enumerate() {
+            samples.push(("GameEvent", to_value(event.clone())));
+            samples.push((
+                "LoggedEvent",
+                to_value(LoggedEvent {
+                    seq: seq as i64,
+                    created: 1_600_000_000,
+                    event,
+                }),
+            ));
+        }
+        samples
+    }
+
+    #[test]
+    fn every_net_type_matches_its_schema() {
+        let spec = spec();
+        for (name, value) in samples() {
+            let schema = &spec["components"]["schemas"][name];
+            assert!(schema.is_object(), "schema {} is not defined", name);
+            if let Err(error) = validate(&spec, schema, &value, name) {
+                panic!("{} does not match its schema: {}", value, error);
+            }
+        }
+    }
+
+    #[test]
+    fn validation_rejects_drift() {
+        let spec = spec();
+        let schema = reference("MuteInfo");
+        let renamed = json!({"user_id": 1, "until": 2, "why": null});
+        assert!(validate(&spec, &schema, &renamed, "MuteInfo").is_err());
+        let not_nullable = json!({"user_id": null, "until": 2, "reason": null});
+        assert!(validate(&spec, &schema, &not_nullable, "MuteInfo").is_err());
+        let unknown_variant = json!({"type": "exploded", "user_id": 1});
+        assert!(validate(
+            &spec,
+            &reference("GameEvent"),
+            &unknown_variant,
+            "GameEvent"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn transcript_documents_every_format() {
+        let spec = spec();
+        let content = &spec["paths"]["/api/v1/sessions/{sid}/transcript"]["get"]["responses"]
+            ["200"]["content"];
+        for media_type in &["application/json", "text/markdown", "text/html"] {
+            assert!(
+                content[media_type].is_object(),
+                "transcript response lacks {}",
+                media_type
+            );
+        }
+    }
+
+    /// the documented example of a request body, checked against its schema and
+    /// parsed into the type of the handler
+    fn parse_example<T: DeserializeOwned>(spec: &Value, name: &str) -> Result<(), String> {
+        let example = spec["components"]["schemas"][name]["example"].clone();
+        validate(spec, &reference(name), &example, name)?;
+        serde_json::from_value::<T>(example)
+            .map(drop)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn request_examples_parse_into_their_types() {
+        let spec = spec();
+        let parsed = vec![
+            (
+                "ConnectData",
+                parse_example::<auth::ConnectData>(&spec, "ConnectData"),
+            ),
+            (
+                "ConnectAdminData",
+                parse_example::<auth::ConnectAdminData>(&spec, "ConnectAdminData"),
+            ),
+            (
+                "ChatPostData",
+                parse_example::<chat::ChatPostData>(&spec, "ChatPostData"),
+            ),
+            (
+                "MuteData",
+                parse_example::<chat::MuteData>(&spec, "MuteData"),
+            ),
+            (
+                "PhaseData",
+                parse_example::<session::PhaseData>(&spec, "PhaseData"),
+            ),
+            (
+                "StateData",
+                parse_example::<session::StateData>(&spec, "StateData"),
+            ),
+            (
+                "SessionSettings",
+                parse_example::<SessionSettings>(&spec, "SessionSettings"),
+            ),
+            ("GameEvent", parse_example::<GameEvent>(&spec, "GameEvent")),
+        ];
+        for (name, result) in &parsed {
+            if let Err(error) = result {
+                panic!("the example of {} doesn't parse: {}", name, error);
+            }
+        }
+
+        for op in OPERATIONS {
+            if let Body::Json(name) = op.request {
+                assert!(
+                    parsed.iter().any(|(parsed, _)| *parsed == name),
+                    "the request body {} of {} has no parsed example",
+                    name,
+                    op.path
+                );
+            }
+        }
+    }
+
+    /// the whole api on a memory storage, like main builds it
+    fn client() -> Client {
+        let db: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let notifier = notify::start(NotifierConfig {
+            bind: ([127, 0, 0, 1], 0).into(),
+            ..Default::default()
+        })
+        .expect("websocket worker");
+        let retention = RetentionConfig {
+            dry_run: true,
+            ..Default::default()
+        };
+        let backups = BackupConfig {
+            dir: std::env::temp_dir().join(format!("werwolf-api-{:08x}", rand::random::<u32>())),
+            keep: 0,
+        };
+        let rocket = rocket::custom(rocket::Config::development())
+            .manage(db)
+            .manage(notifier)
+            .manage(Moderation::new(ModerationConfig::default()))
+            .manage(Arc::new(Retention::new(retention)))
+            .manage(backups);
+        Client::new(mount_current_api_routes(rocket)).expect("valid rocket")
+    }
+
+    /// a value of the documented type, so only a wrong type makes rocket forward
+    fn param_example(name: &str) -> String {
+        if name == "sid" {
+            return "AB12CD34".to_owned();
+        }
+        let schema = param_schema(name);
+        match (schema["type"].as_str(), schema["enum"].get(0)) {
+            (_, Some(first)) => first.as_str().unwrap().to_owned(),
+            (Some("integer"), _) => "1".to_owned(),
+            (Some("boolean"), _) => "true".to_owned(),
+            _ => "newest".to_owned(),
+        }
+    }
+
+    fn example_uri(base: &str, route: &Route) -> String {
+        let path: Vec<String> = full_path(base, route)
+            .split('/')
+            .map(|segment| match param_name(segment) {
+                Some(name) => param_example(name),
+                None => segment.to_owned(),
+            })
+            .collect();
+        let query: Vec<String> = route
+            .uri
+            .query()
+            .unwrap_or("")
+            .split('&')
+            .filter_map(param_name)
+            .map(|name| format!("{}={}", name, param_example(name)))
+            .collect();
+        match query.is_empty() {
+            true => path.join("/"),
+            false => format!("{}?{}", path.join("/"), query.join("&")),
+        }
+    }
+
+    /// messages of the catchers, the handler itself answers with other ones
+    const CAUGHT: &[&str] = &[
+        "The request is malformed",
+        "Missing or invalid auth token",
+        "Not allowed with this auth token",
+        "Nothing found at this address",
+        "The request body doesn't have the expected format",
+        "Internal server error",
+    ];
+
+    /// sends the documented body with the token, the message of a catcher if the
+    /// request didn't reach the handler
+    fn caught(client: &Client, op: &Operation, uri: &str, token: Option<&str>) -> Option<String> {
+        let mut request = client
+            .req(op.method, uri.to_owned())
+            .remote(([127, 0, 0, 1], 4000).into());
+        if let Some(token) = token {
+            request.add_header(Header::new("Authorization", format!("Bearer {}", token)));
+        }
+        if let Body::Json(name) = op.request {
+            request.add_header(ContentType::JSON);
+            request.set_body(spec()["components"]["schemas"][name]["example"].to_string());
+        }
+        let mut response = request.dispatch();
+        let body: Value = serde_json::from_str(&response.body_string()?).ok()?;
+        let message = body["message"].as_str()?;
+        CAUGHT
+            .iter()
+            .find(|caught| **caught == message)
+            .map(|caught| format!("{} ({})", caught, response.status()))
+    }
+
+    #[test]
+    fn documented_requests_reach_their_handlers() {
+        let client = client();
+        let admin = auth::AdminAuthToken::get_jwt();
+        let player = auth::PlayerAuthToken::get_jwt(
+            1,
+            SessionID::try_from("AB12CD34").unwrap(),
+            "Alice".to_owned(),
+            String::new(),
+            PlayerState::Alive,
+        );
+
+        for (base, routes) in current_api_routes() {
+            for route in &routes {
+                let op = find_operation(base, route).unwrap();
+                let uri = example_uri(base, route);
+                let (allowed, refused) = match op.auth {
+                    Auth::Public => (None, None),
+                    Auth::Any => (Some(player.as_str()), None),
+                    Auth::Admin => (Some(admin.as_str()), Some(player.as_str())),
+                };
+
+                if let Some(caught) = caught(&client, op, &uri, allowed) {
+                    panic!(
+                        "{} {} doesn't reach its handler: {}",
+                        op.method, uri, caught
+                    );
+                }
+                if let Some(token) = refused {
+                    assert_eq!(
+                        caught(&client, op, &uri, Some(token)).as_deref(),
+                        Some("Not allowed with this auth token (403 Forbidden)"),
+                        "{} {} is documented as admin only",
+                        op.method,
+                        uri
+                    );
+                }
+                if allowed.is_some() {
+                    assert_eq!(
+                        caught(&client, op, &uri, None).as_deref(),
+                        Some("Missing or invalid auth token (401 Unauthorized)"),
+                        "{} {} is documented to need a token",
+                        op.method,
+                        uri
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_reference_has_a_schema() {
+        let spec = spec();
+        let text = spec.to_string();
+        for rest in text.split("\"#/components/schemas/").skip(1) {
+            let name = &rest[..rest.find('"').unwrap()];
+            assert!(
+                spec["components"]["schemas"][name].is_object(),
+                "schema {} is referenced but not defined",
+                name
+            );
+        }
+    }
+}
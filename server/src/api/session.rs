@@ -1,78 +1,214 @@
+use crate::api::auth::player_token::PlayerState;
+use crate::api::auth::token::AuthLevel;
 use crate::api::auth::{AdminAuthToken, BasicAuthToken};
 use crate::api::auth::{PlayerAuthToken, SessionID};
-use crate::api::net_types::{BasicSessionInfo, PlayerData};
-use crate::database::Database;
+use crate::api::error::ApiError;
+use crate::api::events;
+use crate::api::net_types::{
+    BasicSessionInfo, GameEvent, GamePhase, PlayerData, SessionPage, SessionSettings,
+};
+use crate::database::{SessionCursor, SessionQuery, SessionSort, Storage};
+use crate::notify::{Notification, Notifier};
 use crate::SessionData;
+use log::info;
 use rocket::{Route, State};
 use rocket_contrib::json::Json;
-use serde::Serialize;
+use serde::Deserialize;
 use std::convert::TryFrom;
-use std::ops::Add;
+use std::sync::Arc;
 
 pub fn get_session_api_routes() -> Vec<Route> {
-    routes![get_playerlist, get_all_sessions, get_session_info]
+    routes![
+        get_playerlist,
+        get_all_sessions,
+        get_session_info,
+        set_phase,
+        set_player_state,
+        get_settings,
+        set_settings
+    ]
 }
 
 impl From<SessionData> for BasicSessionInfo {
     fn from(sd: SessionData) -> Self {
         BasicSessionInfo {
-            id: sd.id.to_string(),
-            player_count: 0,
+            id: sd.id.as_str().to_owned(),
+            player_count: sd.player_count,
             active: sd.active,
-            created: sd
-                .created
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            phase: sd.phase,
+            created: sd.created_secs(),
         }
     }
 }
 
-#[get("/")]
-fn get_all_sessions(ctrl: AdminAuthToken, db: State<Database>) -> Json<Vec<BasicSessionInfo>> {
-    let mut locked = db.get_locked_conn();
-    let mut sessions = Database::get_all_sessions(&mut locked, |r| {
-        let secs_unix: i64 = r.get(1)?;
-        Ok(BasicSessionInfo {
-            id: r.get(0)?,
-            player_count: 0,
-            created: secs_unix as u64,
-            active: r.get(2)?,
-        })
-    });
-
-    for s in &mut sessions {
-        // get players in session
-        s.player_count =
-            Database::get_players(&mut locked, &SessionID::try_from(s.id.as_str()).unwrap()).len()
-                as u32;
-    }
+/// page size of the session list if no limit is given
+const DEFAULT_SESSION_PAGE_LEN: u32 = 50;
+
+/// larger pages are cut to this
+const MAX_SESSION_PAGE_LEN: u32 = 500;
 
-    Json(sessions)
+/// admin only: sessions filtered by active and created_after (unix secs), sorted
+/// newest (default), oldest or by players. cursor is next_cursor of the previous page
+#[get("/?<active>&<created_after>&<sort>&<cursor>&<limit>")]
+fn get_all_sessions(
+    active: Option<bool>,
+    created_after: Option<u64>,
+    sort: Option<String>,
+    cursor: Option<String>,
+    limit: Option<u32>,
+    _ctrl: AdminAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<SessionPage>, ApiError> {
+    let sort = match sort {
+        Some(sort) => SessionSort::try_from(sort.as_str()).map_err(ApiError::bad_request)?,
+        None => SessionSort::Newest,
+    };
+    let cursor = match cursor {
+        Some(cursor) => {
+            let cursor = SessionCursor::try_from(cursor.as_str()).map_err(ApiError::bad_request)?;
+            if cursor.sort != sort {
+                return Err(ApiError::bad_request("The cursor belongs to another sort"));
+            }
+            Some(cursor)
+        }
+        None => None,
+    };
+    let limit = limit
+        .unwrap_or(DEFAULT_SESSION_PAGE_LEN)
+        .clamp(1, MAX_SESSION_PAGE_LEN);
+
+    // one more than asked for tells whether there's a next page
+    let mut sessions = db.get_sessions(&SessionQuery {
+        active,
+        created_after,
+        sort,
+        cursor,
+        limit: limit + 1,
+    })?;
+    let next_cursor = if sessions.len() > limit as usize {
+        sessions.truncate(limit as usize);
+        sessions
+            .last()
+            .map(|last| SessionCursor::of(sort, last).to_string())
+    } else {
+        None
+    };
+
+    Ok(Json(SessionPage {
+        sessions: sessions.into_iter().map(BasicSessionInfo::from).collect(),
+        next_cursor,
+    }))
 }
 
 #[get("/<sid>/playerlist", format = "json")]
 fn get_playerlist(
     sid: SessionID,
     auth: BasicAuthToken,
-    db: State<Database>,
-) -> Option<Json<Vec<PlayerData>>> {
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+) -> Result<Json<Vec<PlayerData>>, ApiError> {
     if let Ok(player_auth) = PlayerAuthToken::try_from(auth) {
         if player_auth.session_id != sid {
-            return None;
+            return Err(ApiError::forbidden("Not a player of this session"));
         }
     }
-    Some(Json(Database::get_players(&mut db.get_locked_conn(), &sid)))
+    let mut players = db.get_players(&sid)?;
+    for player in &mut players {
+        player.presence = notifier.presence(player.user_id);
+    }
+    Ok(Json(players))
 }
 
 #[get("/<sid>", format = "json")]
 fn get_session_info(
+    sid: SessionID,
+    _auth: BasicAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<BasicSessionInfo>, ApiError> {
+    Ok(Json(db.get_session_data(&sid)?.into()))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PhaseData {
+    phase: GamePhase,
+}
+
+/// admins move the game between lobby, day, night and ended
+#[put("/<sid>/phase", data = "<phase_data>")]
+fn set_phase(
+    sid: SessionID,
+    _auth: AdminAuthToken,
+    phase_data: Json<PhaseData>,
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+) -> Result<(), ApiError> {
+    let phase = phase_data.into_inner().phase;
+
+    events::apply(&**db, &sid, GameEvent::PhaseChanged { phase }, &notifier)?;
+    info!("{} is now in phase {}", sid, phase.as_str());
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StateData {
+    state: PlayerState,
+}
+
+/// admins kill, revive or move players to the spectators
+#[put("/<sid>/players/<user_id>/state", data = "<state_data>")]
+fn set_player_state(
+    sid: SessionID,
+    user_id: u32,
+    _auth: AdminAuthToken,
+    state_data: Json<StateData>,
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+) -> Result<(), ApiError> {
+    let state = state_data.into_inner().state;
+    let event = match state {
+        PlayerState::Dead => GameEvent::Died {
+            user_id,
+            cause: None,
+        },
+        state => GameEvent::StateChanged { user_id, state },
+    };
+
+    events::apply(&**db, &sid, event, &notifier)?;
+    info!("Player {} in {} is now {}", user_id, sid, state.as_str());
+    Ok(())
+}
+
+/// players need the settings too, e.g. to know if whispers are enabled
+#[get("/<sid>/settings", format = "json")]
+fn get_settings(
     sid: SessionID,
     auth: BasicAuthToken,
-    db: State<Database>,
-) -> Option<Json<BasicSessionInfo>> {
-    match Database::get_session_data(&mut db.get_locked_conn(), &sid) {
-        Some(sd) => Some(Json(sd.into())),
-        None => None,
+    db: State<Arc<dyn Storage>>,
+) -> Result<Json<SessionSettings>, ApiError> {
+    if auth.auth_level() != AuthLevel::Control {
+        match PlayerAuthToken::try_from(auth) {
+            Ok(player_auth) if player_auth.session_id == sid => {}
+            Ok(_) => return Err(ApiError::forbidden("Not a player of this session")),
+            Err(_) => return Err(ApiError::forbidden("Invalid player token")),
+        }
     }
+    Ok(Json(db.get_session_data(&sid)?.settings()))
+}
+
+#[put("/<sid>/settings", data = "<settings>")]
+fn set_settings(
+    sid: SessionID,
+    _auth: AdminAuthToken,
+    settings: Json<SessionSettings>,
+    db: State<Arc<dyn Storage>>,
+    notifier: State<Notifier>,
+) -> Result<(), ApiError> {
+    db.set_session_settings(&sid, &settings)?;
+
+    info!("Updated settings of {}", sid);
+    notifier.send(Notification::CustomToSession(
+        sid,
+        "update.settings".to_owned(),
+    ));
+    Ok(())
 }
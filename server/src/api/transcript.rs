@@ -0,0 +1,221 @@
+use crate::api::auth::{AdminAuthToken, SessionID};
+use crate::api::chat::escape_html;
+use crate::api::error::ApiError;
+use crate::api::net_types::{ChatChannel, ChatMessage, GamePhase, PlayerData};
+use crate::database::{ChatQuery, Storage};
+use rocket::http::{ContentType, Status};
+use rocket::response::content;
+use rocket::{Route, State};
+use serde::Serialize;
+use std::sync::Arc;
+
+// mounted to /api/v1/sessions/ next to the session routes
+pub fn get_transcript_api_routes() -> Vec<Route> {
+    routes![get_transcript]
+}
+
+/// complete chat of a session including all whispers and system events
+#[derive(Serialize)]
+pub(crate) struct Transcript {
+    pub(crate) session_id: String,
+    pub(crate) created: u64,
+    pub(crate) players: Vec<TranscriptPlayer>,
+    pub(crate) messages: Vec<ChatMessage>,
+}
+
+/// a player as of the end of the game, without the live presence of PlayerData
+#[derive(Serialize)]
+pub(crate) struct TranscriptPlayer {
+    pub(crate) user_id: u32,
+    pub(crate) name: String,
+    pub(crate) role: Option<String>,
+    pub(crate) joined: u64,
+    pub(crate) state: String,
+}
+
+impl From<PlayerData> for TranscriptPlayer {
+    fn from(player: PlayerData) -> Self {
+        TranscriptPlayer {
+            user_id: player.user_id,
+            name: player.name,
+            role: player.role,
+            joined: player.joined,
+            state: player.state,
+        }
+    }
+}
+
+/// admin only: the whole transcript as json, markdown or html (default json).
+/// 409 while the game still runs, once it ended or the session was closed it is final
+#[get("/<sid>/transcript?<format>")]
+fn get_transcript(
+    sid: SessionID,
+    format: Option<String>,
+    _auth: AdminAuthToken,
+    db: State<Arc<dyn Storage>>,
+) -> Result<content::Content<String>, ApiError> {
+    let session = db.get_session_data(&sid)?;
+    if session.active && session.phase != GamePhase::Ended {
+        return Err(ApiError::new(
+            Status::Conflict,
+            "The game is still running, the transcript is available once it ended",
+        ));
+    }
+
+    let transcript = Transcript {
+        session_id: sid.as_str().to_owned(),
+        created: session
+            .created
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        players: db
+            .get_players(&sid)?
+            .into_iter()
+            .map(TranscriptPlayer::from)
+            .collect(),
+        // system events are stored as chat messages, so they are already in order.
+        // Messages are stored escaped, every format starts from the raw text
+        messages: db
+            .get_chat_messages(&sid, &ChatQuery::default())?
+            .into_iter()
+            .map(|mut msg| {
+                msg.message = unescape_html(&msg.message);
+                msg
+            })
+            .collect(),
+    };
+
+    match format.as_deref().unwrap_or("json") {
+        "json" => serde_json::to_string_pretty(&transcript)
+            .map(|json| content::Content(ContentType::JSON, json))
+            .map_err(|e| ApiError::internal(e.to_string())),
+        "markdown" => Ok(content::Content(
+            ContentType::new("text", "markdown"),
+            to_markdown(&transcript),
+        )),
+        "html" => Ok(content::Content(ContentType::HTML, to_html(&transcript))),
+        other => Err(ApiError::bad_request(format!(
+            "Unknown format {}, use json, markdown or html",
+            other
+        ))),
+    }
+}
+
+fn channel_label(msg: &ChatMessage) -> String {
+    match (msg.channel, &msg.to_name) {
+        (ChatChannel::Whisper, Some(to)) => format!("whisper to {}", to),
+        (channel, _) => channel.as_str().to_owned(),
+    }
+}
+
+/// messages are stored escaped, the transcript gets the text back
+fn unescape_html(escaped: &str) -> String {
+    escaped
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+fn to_markdown(transcript: &Transcript) -> String {
+    let mut md = format!(
+        "# Transcript {}\n\nCreated {}\n\n## Players\n\n",
+        transcript.session_id,
+        format_utc(transcript.created)
+    );
+    for player in &transcript.players {
+        md.push_str(&format!(
+            "- {} ({}, {})\n",
+            player.name,
+            player.role.as_deref().unwrap_or("no role"),
+            player.state
+        ));
+    }
+
+    md.push_str("\n## Chat\n\n");
+    for msg in &transcript.messages {
+        let text = &msg.message;
+        if msg.channel == ChatChannel::System {
+            md.push_str(&format!("- `{}` *{}*\n", format_utc(msg.send_date), text));
+        } else {
+            md.push_str(&format!(
+                "- `{}` [{}] **{}**: {}\n",
+                format_utc(msg.send_date),
+                channel_label(msg),
+                msg.sender_name,
+                text
+            ));
+        }
+    }
+    md
+}
+
+fn to_html(transcript: &Transcript) -> String {
+    let sid = escape_html(&transcript.session_id);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"UTF-8\">\n<title>Transcript {}</title>\n\
+        </head>\n<body>\n<h1>Transcript {}</h1>\n<p>Created {}</p>\n<h2>Players</h2>\n<ul>\n",
+        sid,
+        sid,
+        format_utc(transcript.created)
+    );
+    for player in &transcript.players {
+        html.push_str(&format!(
+            "<li>{} ({}, {})</li>\n",
+            escape_html(&player.name),
+            escape_html(player.role.as_deref().unwrap_or("no role")),
+            escape_html(&player.state)
+        ));
+    }
+
+    html.push_str("</ul>\n<h2>Chat</h2>\n<ul>\n");
+    for msg in &transcript.messages {
+        if msg.channel == ChatChannel::System {
+            html.push_str(&format!(
+                "<li class=\"system\"><time>{}</time> <em>{}</em></li>\n",
+                format_utc(msg.send_date),
+                escape_html(&msg.message)
+            ));
+        } else {
+            html.push_str(&format!(
+                "<li class=\"{}\"><time>{}</time> [{}] <b>{}</b>: {}</li>\n",
+                msg.channel.as_str(),
+                format_utc(msg.send_date),
+                escape_html(&channel_label(msg)),
+                escape_html(&msg.sender_name),
+                escape_html(&msg.message)
+            ));
+        }
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+/// unix secs as "YYYY-MM-DD HH:MM:SS UTC"
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
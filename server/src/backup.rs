@@ -0,0 +1,268 @@
+use crate::api::net_types::BackupInfo;
+use crate::database::{DbError, Storage};
+use log::{info, warn};
+use rusqlite::backup::Progress;
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// log target of everything backup related
+pub const BACKUP_LOG_TARGET: &str = "Backup";
+
+const PREFIX: &str = "backup-";
+const SUFFIX: &str = ".sqlite";
+
+pub struct BackupConfig {
+    pub dir: PathBuf,
+    /// older backups get deleted after a new one was written, 0 keeps all
+    pub keep: usize,
+}
+
+/// everything creating, listing or restoring backups can fail with
+#[derive(Debug)]
+pub enum BackupError {
+    /// there is no backup of that name
+    NotFound(String),
+    /// reading the database or writing the copy failed
+    Db(DbError),
+    /// the backup directory or a file in it
+    Io(String),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackupError::NotFound(name) => write!(f, "No backup named {}", name),
+            BackupError::Db(e) => write!(f, "{}", e),
+            BackupError::Io(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<DbError> for BackupError {
+    fn from(e: DbError) -> Self {
+        BackupError::Db(e)
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Db(e.into())
+    }
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: PathBuf::from("backups"),
+            keep: 10,
+        }
+    }
+}
+
+impl BackupConfig {
+    /// reads BACKUP_DIR and BACKUP_KEEP
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = BackupConfig::default();
+
+        if let Ok(dir) = std::env::var("BACKUP_DIR") {
+            config.dir = PathBuf::from(dir);
+        }
+        if let Ok(keep) = std::env::var("BACKUP_KEEP") {
+            config.keep = keep
+                .parse()
+                .map_err(|_| format!("BACKUP_KEEP must be a number, not {}", keep))?;
+        }
+
+        Ok(config)
+    }
+
+    /// backups named like ours, newest first
+    pub fn list(&self) -> Result<Vec<BackupInfo>, BackupError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(BackupError::Io(format!(
+                    "Failed to read {:?}: {}",
+                    self.dir, e
+                )))
+            }
+        };
+
+        let mut backups: Vec<BackupInfo> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                if name.len() < PREFIX.len() + SUFFIX.len()
+                    || !name.starts_with(PREFIX)
+                    || !name.ends_with(SUFFIX)
+                {
+                    return None;
+                }
+                let created = name[PREFIX.len()..name.len() - SUFFIX.len()].parse().ok()?;
+                Some(BackupInfo {
+                    size: entry.metadata().ok()?.len(),
+                    name,
+                    created,
+                })
+            })
+            .collect();
+        backups.sort_by(|a, b| b.created.cmp(&a.created));
+        Ok(backups)
+    }
+
+    /// path of an existing backup, only names from the list are accepted
+    fn find(&self, name: &str) -> Result<PathBuf, BackupError> {
+        if self.list()?.iter().any(|backup| backup.name == name) {
+            Ok(self.dir.join(name))
+        } else {
+            Err(BackupError::NotFound(name.to_owned()))
+        }
+    }
+
+    /// creates an empty file for a backup taken now. Creating it fails if the name is
+    /// taken, so two backups in the same second can't pick the same file
+    fn next_path(&self) -> Result<PathBuf, BackupError> {
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| BackupError::Io(format!("Failed to create {:?}: {}", self.dir, e)))?;
+
+        let mut created = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        loop {
+            let path = self.dir.join(format!("{}{}{}", PREFIX, created, SUFFIX));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(path),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => created += 1,
+                Err(e) => {
+                    return Err(BackupError::Io(format!(
+                        "Failed to create {:?}: {}",
+                        path, e
+                    )))
+                }
+            }
+        }
+    }
+
+    /// deletes the oldest backups beyond keep
+    fn rotate(&self) -> Result<(), BackupError> {
+        if self.keep == 0 {
+            return Ok(());
+        }
+        for old in self.list()?.iter().skip(self.keep) {
+            let path = self.dir.join(&old.name);
+            match std::fs::remove_file(&path) {
+                Ok(()) => info!(target: BACKUP_LOG_TARGET, "Deleted old backup {}", old.name),
+                Err(e) => warn!(target: BACKUP_LOG_TARGET, "Failed to delete {:?}: {}", path, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// writes a consistent copy of the running database and rotates the old ones
+pub fn create(db: &dyn Storage, config: &BackupConfig) -> Result<BackupInfo, BackupError> {
+    let path = config.next_path()?;
+    let written = db.backup(&path).map_err(BackupError::from);
+    finish(config, &path, written)
+}
+
+/// like create, but straight from the database file. Works next to a running server
+pub fn create_from_file(db_path: &Path, config: &BackupConfig) -> Result<BackupInfo, BackupError> {
+    let path = config.next_path()?;
+    let written = copy_database(db_path, &path);
+    finish(config, &path, written)
+}
+
+/// removes the file of a failed backup, so it isn't listed as one
+fn finish(
+    config: &BackupConfig,
+    path: &Path,
+    written: Result<(), BackupError>,
+) -> Result<BackupInfo, BackupError> {
+    if let Err(e) = written {
+        warn!(target: BACKUP_LOG_TARGET, "Backup to {:?} failed: {}", path, e);
+        let _ = std::fs::remove_file(path);
+        return Err(e);
+    }
+    info!(target: BACKUP_LOG_TARGET, "Wrote backup {:?}", path);
+    config.rotate()?;
+
+    let name = path.file_name().unwrap().to_string_lossy();
+    config
+        .list()?
+        .into_iter()
+        .find(|backup| backup.name == name)
+        .ok_or_else(|| BackupError::Io(format!("Backup {:?} disappeared", path)))
+}
+
+/// replaces the database with a backup, only before the server opened it.
+/// The current database is backed up first, so a wrong choice can be undone
+pub fn restore(db_path: &Path, name: &str, config: &BackupConfig) -> Result<(), BackupError> {
+    let src = config.find(name)?;
+
+    // rotating now could delete the backup to restore
+    if db_path.exists() {
+        let previous = config.next_path()?;
+        if let Err(e) = copy_database(db_path, &previous) {
+            let _ = std::fs::remove_file(&previous);
+            return Err(e);
+        }
+        info!(
+            target: BACKUP_LOG_TARGET,
+            "Saved the current database as {:?} before restoring", previous
+        );
+    }
+
+    let mut conn = Connection::open(db_path)?;
+    conn.restore(DatabaseName::Main, &src, None::<fn(Progress)>)?;
+    info!(target: BACKUP_LOG_TARGET, "Restored {} into {:?}", name, db_path);
+    config.rotate()
+}
+
+/// sqlite online backup from one file into another
+fn copy_database(src: &Path, dst: &Path) -> Result<(), BackupError> {
+    let conn = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    conn.backup(DatabaseName::Main, dst, None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::MemoryStorage;
+
+    fn temp_config() -> BackupConfig {
+        let dir = format!("werwolf-backups-{:08x}", rand::random::<u32>());
+        BackupConfig {
+            dir: std::env::temp_dir().join(dir),
+            keep: 0,
+        }
+    }
+
+    #[test]
+    fn backups_in_the_same_second_get_their_own_file() {
+        let config = temp_config();
+        let first = config.next_path().unwrap();
+        let second = config.next_path().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(config.list().unwrap().len(), 2);
+        std::fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn failed_backups_leave_no_file() {
+        let config = temp_config();
+        let result = create(&MemoryStorage::new(), &config);
+        assert!(matches!(result, Err(BackupError::Db(_))));
+        assert!(config.list().unwrap().is_empty());
+        assert!(matches!(
+            config.find("backup-1.sqlite"),
+            Err(BackupError::NotFound(_))
+        ));
+        std::fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
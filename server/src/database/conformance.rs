@@ -0,0 +1,261 @@
+//! checks every backend has to pass, so the memory storage behaves like sqlite
+
+use super::*;
+use crate::api::auth::player_token::PlayerState;
+use std::path::PathBuf;
+
+/// a sqlite file in the temp dir, removed with its WAL files on drop
+struct TempSqlite(PathBuf);
+
+impl TempSqlite {
+    fn new() -> Self {
+        let name = format!("werwolf-test-{:08x}.sqlite", rand::random::<u32>());
+        TempSqlite(std::env::temp_dir().join(name))
+    }
+}
+
+impl Drop for TempSqlite {
+    fn drop(&mut self) {
+        for suffix in &["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// runs the check against a fresh storage of every backend
+fn each_backend(check: fn(&dyn Storage)) {
+    check(&MemoryStorage::new());
+
+    let file = TempSqlite::new();
+    check(&SqliteStorage::open(&file.0).unwrap());
+}
+
+fn sid(id: &str) -> SessionID {
+    SessionID::try_from(id).unwrap()
+}
+
+fn is_conflict<T>(result: Result<T, DbError>) -> bool {
+    matches!(result, Err(DbError::Conflict(_)))
+}
+
+fn is_not_found<T>(result: Result<T, DbError>) -> bool {
+    matches!(result, Err(DbError::NotFound(_)))
+}
+
+#[test]
+fn names_are_unique_per_session() {
+    each_backend(|db| {
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        db.create_session(&sid("BBBBBBBB")).unwrap();
+
+        db.maybe_add_player("anna", &sid("AAAAAAAA")).unwrap();
+        assert!(is_conflict(db.maybe_add_player("anna", &sid("AAAAAAAA"))));
+        db.maybe_add_player("anna", &sid("BBBBBBBB")).unwrap();
+        db.maybe_add_player("bert", &sid("AAAAAAAA")).unwrap();
+
+        assert_eq!(db.get_players(&sid("AAAAAAAA")).unwrap().len(), 2);
+        let stats = db.get_stats(0).unwrap();
+        assert_eq!(stats.players_active, 3);
+        assert_eq!(stats.unique_users, 3);
+        assert_eq!(
+            db.get_session_data(&sid("BBBBBBBB")).unwrap().player_count,
+            1
+        );
+    });
+}
+
+#[test]
+fn sessions_are_created_once() {
+    each_backend(|db| {
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        assert!(is_conflict(db.create_session(&sid("AAAAAAAA"))));
+
+        let session = db.get_session_data(&sid("AAAAAAAA")).unwrap();
+        assert!(session.active);
+        assert_eq!(session.phase, GamePhase::Lobby);
+        assert!(is_not_found(db.get_session_data(&sid("CCCCCCCC"))));
+    });
+}
+
+#[test]
+fn players_only_join_active_sessions() {
+    each_backend(|db| {
+        assert!(is_not_found(db.maybe_add_player("anna", &sid("AAAAAAAA"))));
+
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        let future = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() + 60;
+        let cutoffs = RetentionCutoffs {
+            idle_before: future,
+            closed_before: 0,
+            chat_before: 0,
+        };
+        let report = db.cleanup(&cutoffs, false).unwrap();
+        assert_eq!(report.sessions_deactivated, 1);
+        assert_eq!(report.deactivated, vec![sid("AAAAAAAA")]);
+        assert_eq!(report.sessions_deleted, 0);
+
+        assert!(!db.get_session_data(&sid("AAAAAAAA")).unwrap().active);
+        assert!(is_conflict(db.maybe_add_player("anna", &sid("AAAAAAAA"))));
+    });
+}
+
+#[test]
+fn cleanup_deletes_closed_sessions() {
+    each_backend(|db| {
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        db.create_session(&sid("BBBBBBBB")).unwrap();
+        db.maybe_add_player("anna", &sid("AAAAAAAA")).unwrap();
+        db.add_chat_message(&sid("AAAAAAAA"), None, ChatChannel::Village, None, "hi")
+            .unwrap();
+
+        let future = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() + 60;
+        let cutoffs = RetentionCutoffs {
+            idle_before: future,
+            closed_before: future,
+            chat_before: 0,
+        };
+        let dry = db.cleanup(&cutoffs, true).unwrap();
+        assert_eq!(dry.sessions_deleted, 2);
+        assert!(db.get_session_data(&sid("AAAAAAAA")).unwrap().active);
+
+        let mut report = db.cleanup(&cutoffs, false).unwrap();
+        report.deleted.sort_by_key(|sid| sid.to_string());
+        assert_eq!(report.deleted, vec![sid("AAAAAAAA"), sid("BBBBBBBB")]);
+        assert_eq!(report.players_deleted, 1);
+        assert_eq!(report.messages_deleted, 1);
+        assert_eq!(report.events_deleted, 1);
+        assert!(is_not_found(db.get_session_data(&sid("AAAAAAAA"))));
+        assert!(db.get_events(&sid("AAAAAAAA")).unwrap().is_empty());
+    });
+}
+
+#[test]
+fn events_update_the_session_and_players() {
+    each_backend(|db| {
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        let anna = db.maybe_add_player("anna", &sid("AAAAAAAA")).unwrap();
+
+        let role = GameEvent::RoleAssigned {
+            user_id: anna,
+            role: "seer".to_owned(),
+        };
+        db.apply_event(&sid("AAAAAAAA"), role).unwrap();
+        let phase = GameEvent::PhaseChanged {
+            phase: GamePhase::Night,
+        };
+        db.apply_event(&sid("AAAAAAAA"), phase).unwrap();
+
+        let player = db.get_player(&sid("AAAAAAAA"), anna).unwrap();
+        assert_eq!(player.role.as_deref(), Some("seer"));
+        assert_eq!(player.state, PlayerState::Alive.as_str());
+        let session = db.get_session_data(&sid("AAAAAAAA")).unwrap();
+        assert_eq!(session.phase, GamePhase::Night);
+
+        let kinds: Vec<&str> = db
+            .get_events(&sid("AAAAAAAA"))
+            .unwrap()
+            .iter()
+            .map(|logged| logged.event.kind())
+            .collect();
+        assert_eq!(kinds, vec!["joined", "role_assigned", "phase_changed"]);
+        assert!(is_not_found(
+            db.get_player(&sid("AAAAAAAA"), anna.wrapping_add(1))
+        ));
+    });
+}
+
+#[test]
+fn chat_is_paged_in_order() {
+    each_backend(|db| {
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        db.create_session(&sid("BBBBBBBB")).unwrap();
+        let anna = db.maybe_add_player("anna", &sid("AAAAAAAA")).unwrap();
+        let bert = db.maybe_add_player("bert", &sid("AAAAAAAA")).unwrap();
+
+        let mut ids = Vec::new();
+        for text in &["1", "2", "3", "4"] {
+            let msg = db
+                .add_chat_message(
+                    &sid("AAAAAAAA"),
+                    Some(anna),
+                    ChatChannel::Village,
+                    None,
+                    text,
+                )
+                .unwrap();
+            ids.push(msg.message_id);
+        }
+        let whisper = db
+            .add_chat_message(
+                &sid("AAAAAAAA"),
+                Some(anna),
+                ChatChannel::Whisper,
+                Some(bert),
+                "psst",
+            )
+            .unwrap();
+        assert_eq!(whisper.sender_name, "anna");
+        assert_eq!(whisper.to_name.as_deref(), Some("bert"));
+        let admin = db
+            .add_chat_message(&sid("BBBBBBBB"), None, ChatChannel::Village, None, "hi")
+            .unwrap();
+        assert_eq!(admin.sender_name, ADMIN_SENDER_NAME);
+
+        let texts = |query: ChatQuery| -> Vec<String> {
+            db.get_chat_messages(&sid("AAAAAAAA"), &query)
+                .unwrap()
+                .into_iter()
+                .map(|msg| msg.message)
+                .collect()
+        };
+        let village = || Some(vec![ChatChannel::Village]);
+
+        assert_eq!(
+            texts(ChatQuery::default()),
+            vec!["1", "2", "3", "4", "psst"]
+        );
+        let newest = ChatQuery {
+            channels: village(),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(texts(newest), vec!["3", "4"]);
+        let older = ChatQuery {
+            before: Some(ids[2]),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(texts(older), vec!["2"]);
+        let newer = ChatQuery {
+            after: Some(ids[0]),
+            channels: village(),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(texts(newer), vec!["2", "3"]);
+        let stranger = (0..3).find(|id| *id != anna && *id != bert).unwrap();
+        let others = ChatQuery {
+            whispers_of: Some(stranger),
+            ..Default::default()
+        };
+        assert_eq!(texts(others), vec!["1", "2", "3", "4"]);
+    });
+}
+
+#[test]
+fn chat_needs_an_existing_session() {
+    each_backend(|db| {
+        let msg = db.add_chat_message(&sid("AAAAAAAA"), None, ChatChannel::Village, None, "hi");
+        assert!(is_not_found(msg));
+
+        db.create_session(&sid("AAAAAAAA")).unwrap();
+        db.add_chat_message(&sid("AAAAAAAA"), None, ChatChannel::Village, None, "hi")
+            .unwrap();
+        assert!(db
+            .get_chat_messages(&sid("BBBBBBBB"), &ChatQuery::default())
+            .unwrap()
+            .is_empty());
+    });
+}
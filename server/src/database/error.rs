@@ -0,0 +1,70 @@
+use rusqlite::{ffi, ErrorCode};
+use std::fmt;
+
+/// everything a storage call can fail with, routes map it to a status code
+#[derive(Debug)]
+pub enum DbError {
+    /// the session, player or record doesn't exist
+    NotFound(String),
+    /// the request contradicts the stored state, e.g. a taken name or an inactive session
+    Conflict(String),
+    /// a check of the schema rejected the write
+    Constraint(String),
+    /// the database stayed locked for longer than the busy timeout
+    Busy,
+    /// stored data or the schema can't be read
+    Corrupt(String),
+    /// the file or connection failed
+    Io(String),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::NotFound(msg) => write!(f, "{}", msg),
+            DbError::Conflict(msg) => write!(f, "{}", msg),
+            DbError::Constraint(msg) => write!(f, "Constraint failed: {}", msg),
+            DbError::Busy => write!(f, "Database is busy"),
+            DbError::Corrupt(msg) => write!(f, "Corrupt data: {}", msg),
+            DbError::Io(msg) => write!(f, "Database error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => DbError::NotFound("No such entry".into()),
+            rusqlite::Error::SqliteFailure(err, msg) => {
+                let msg = msg.unwrap_or_else(|| err.to_string());
+                match err.code {
+                    ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => DbError::Busy,
+                    // duplicates are a conflict with an existing entry
+                    ErrorCode::ConstraintViolation
+                        if err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE
+                            || err.extended_code == ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+                    {
+                        DbError::Conflict(msg)
+                    }
+                    ErrorCode::ConstraintViolation => DbError::Constraint(msg),
+                    ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => DbError::Corrupt(msg),
+                    _ => DbError::Io(msg),
+                }
+            }
+            rusqlite::Error::FromSqlConversionFailure(..)
+            | rusqlite::Error::IntegralValueOutOfRange(..)
+            | rusqlite::Error::InvalidColumnType(..)
+            | rusqlite::Error::Utf8Error(_) => DbError::Corrupt(e.to_string()),
+            e => DbError::Io(e.to_string()),
+        }
+    }
+}
+
+/// json columns like settings and game events
+impl From<serde_json::Error> for DbError {
+    fn from(e: serde_json::Error) -> Self {
+        DbError::Corrupt(e.to_string())
+    }
+}
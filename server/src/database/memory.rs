@@ -0,0 +1,512 @@
+use super::{
+    ChatQuery, DbError, RetentionCutoffs, SessionQuery, SessionSort, Storage, StorageStats,
+    ADMIN_SENDER_NAME, USER_ID_ATTEMPTS,
+};
+use crate::api::auth::player_token::PlayerState;
+use crate::api::auth::SessionID;
+use crate::api::net_types::{
+    ArchivedGame, ArchivedGameInfo, ChatChannel, ChatMessage, CleanupReport, GameEvent, GamePhase,
+    LoggedEvent, PlayerData, Presence, SessionSettings,
+};
+use crate::game_log;
+use crate::SessionData;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+struct StoredSession {
+    created: SystemTime,
+    active: bool,
+    settings: Option<String>,
+    phase: GamePhase,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    sessions: HashMap<SessionID, StoredSession>,
+    /// in join order, like the rows of the users table
+    players: Vec<(SessionID, PlayerData)>,
+    chat: Vec<(SessionID, ChatMessage)>,
+    last_message_id: i64,
+    events: Vec<(SessionID, LoggedEvent)>,
+    last_event_seq: i64,
+    archive: Vec<ArchivedGame>,
+}
+
+/// keeps everything in memory and loses it on restart,
+/// for tests and dev servers without a database file
+#[derive(Default)]
+pub struct MemoryStorage(Mutex<MemoryData>);
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// every change is a single step that can't panic halfway
+    fn data(&self) -> MutexGuard<MemoryData> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn unix_secs() -> u64 {
+    std::time::UNIX_EPOCH.elapsed().unwrap().as_secs()
+}
+
+impl MemoryData {
+    fn log_event(&mut self, sid: &SessionID, event: GameEvent) -> LoggedEvent {
+        self.last_event_seq += 1;
+        let logged = LoggedEvent {
+            seq: self.last_event_seq,
+            created: unix_secs(),
+            event,
+        };
+        self.events.push((*sid, logged.clone()));
+        logged
+    }
+
+    fn player_mut(&mut self, sid: &SessionID, user_id: u32) -> Result<&mut PlayerData, DbError> {
+        self.players
+            .iter_mut()
+            .find(|(player_sid, player)| player_sid == sid && player.user_id == user_id)
+            .map(|(_, player)| player)
+            .ok_or_else(|| DbError::NotFound("Player not found".into()))
+    }
+
+    /// replaces the record of the same game if it was archived before
+    fn archive_game(&mut self, mut game: ArchivedGame) {
+        match self
+            .archive
+            .iter_mut()
+            .find(|old| old.session_id == game.session_id && old.started == game.started)
+        {
+            Some(old) => {
+                game.game_id = old.game_id;
+                *old = game;
+            }
+            None => {
+                game.game_id = self.archive.last().map(|g| g.game_id).unwrap_or(0) + 1;
+                self.archive.push(game);
+            }
+        }
+    }
+
+    /// unix secs of the newest message, event or join, like LAST_ACTIVITY of the sqlite storage
+    fn last_activity(&self, sid: &SessionID, session: &StoredSession) -> u64 {
+        let created = session
+            .created
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let chat = self
+            .chat
+            .iter()
+            .filter(|(msg_sid, _)| msg_sid == sid)
+            .map(|(_, msg)| msg.send_date);
+        let joins = self
+            .players
+            .iter()
+            .filter(|(player_sid, _)| player_sid == sid)
+            .map(|(_, player)| player.joined);
+        let events = self
+            .events
+            .iter()
+            .filter(|(event_sid, _)| event_sid == sid)
+            .map(|(_, logged)| logged.created);
+        chat.chain(joins).chain(events).fold(created, u64::max)
+    }
+
+    fn session_mut(&mut self, sid: &SessionID) -> Result<&mut StoredSession, DbError> {
+        self.sessions
+            .get_mut(sid)
+            .ok_or_else(|| DbError::NotFound("Session doesn't exist".into()))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn create_session(&self, sid: &SessionID) -> Result<(), DbError> {
+        let mut data = self.data();
+        if data.sessions.contains_key(sid) {
+            return Err(DbError::Conflict("Session already exists".into()));
+        }
+        data.sessions.insert(
+            *sid,
+            StoredSession {
+                created: SystemTime::now(),
+                active: true,
+                settings: None,
+                phase: GamePhase::Lobby,
+            },
+        );
+        Ok(())
+    }
+
+    fn get_session_data(&self, sid: &SessionID) -> Result<SessionData, DbError> {
+        let data = self.data();
+        data.sessions
+            .get(sid)
+            .map(|session| SessionData {
+                id: *sid,
+                created: session.created,
+                active: session.active,
+                settings: session.settings.clone(),
+                phase: session.phase,
+                player_count: data.players.iter().filter(|(s, _)| s == sid).count() as u32,
+            })
+            .ok_or_else(|| DbError::NotFound("Session doesn't exist".into()))
+    }
+
+    fn get_sessions(&self, query: &SessionQuery) -> Result<Vec<SessionData>, DbError> {
+        let data = self.data();
+        let mut player_counts: HashMap<SessionID, u32> = HashMap::new();
+        for (sid, _) in &data.players {
+            *player_counts.entry(*sid).or_insert(0) += 1;
+        }
+
+        let mut sessions: Vec<SessionData> = data
+            .sessions
+            .iter()
+            .map(|(sid, session)| SessionData {
+                id: *sid,
+                created: session.created,
+                active: session.active,
+                settings: session.settings.clone(),
+                phase: session.phase,
+                player_count: player_counts.get(sid).copied().unwrap_or(0),
+            })
+            .filter(|s| query.active.map_or(true, |active| s.active == active))
+            .filter(|s| {
+                query
+                    .created_after
+                    .map_or(true, |after| s.created_secs() > after)
+            })
+            .filter(|s| {
+                query
+                    .cursor
+                    .map_or(true, |cursor| query.sort.is_after(s, &cursor))
+            })
+            .collect();
+
+        sessions.sort_by(|a, b| {
+            let order = (query.sort.key(a), a.id.as_str()).cmp(&(query.sort.key(b), b.id.as_str()));
+            match query.sort {
+                SessionSort::Oldest => order,
+                SessionSort::Newest | SessionSort::Players => order.reverse(),
+            }
+        });
+        sessions.truncate(query.limit as usize);
+        Ok(sessions)
+    }
+
+    fn get_stats(&self, messages_since: u64) -> Result<StorageStats, DbError> {
+        let data = self.data();
+        let is_active = |sid: &SessionID| data.sessions.get(sid).map_or(false, |s| s.active);
+        Ok(StorageStats {
+            sessions_active: data.sessions.values().filter(|s| s.active).count() as u32,
+            players_active: data
+                .players
+                .iter()
+                .filter(|(sid, _)| is_active(sid))
+                .count() as u32,
+            unique_users: data
+                .players
+                .iter()
+                .map(|(_, player)| player.user_id)
+                .collect::<HashSet<u32>>()
+                .len() as u32,
+            games_in_progress: data
+                .sessions
+                .values()
+                .filter(|s| s.active && (s.phase == GamePhase::Day || s.phase == GamePhase::Night))
+                .count() as u32,
+            messages_since: data
+                .chat
+                .iter()
+                .filter(|(_, msg)| msg.send_date >= messages_since)
+                .count() as u32,
+        })
+    }
+
+    fn set_session_settings(
+        &self,
+        sid: &SessionID,
+        settings: &SessionSettings,
+    ) -> Result<(), DbError> {
+        let json = serde_json::to_string(settings)?;
+        self.data().session_mut(sid)?.settings = Some(json);
+        Ok(())
+    }
+
+    fn maybe_add_player(&self, name: &str, sid: &SessionID) -> Result<u32, DbError> {
+        let mut data = self.data();
+
+        if !data.session_mut(sid)?.active {
+            return Err(DbError::Conflict("Session inactive".into()));
+        }
+
+        let name_taken = data
+            .players
+            .iter()
+            .any(|(player_sid, player)| player_sid == sid && player.name == name);
+        if name_taken {
+            return Err(DbError::Conflict("A user with the same name exists".into()));
+        }
+
+        let id = (0..USER_ID_ATTEMPTS)
+            .map(|_| rand::random::<u32>())
+            .find(|id| !data.players.iter().any(|(_, player)| player.user_id == *id))
+            .ok_or_else(|| {
+                DbError::Conflict(format!(
+                    "No free user_id found in {} tries",
+                    USER_ID_ATTEMPTS
+                ))
+            })?;
+
+        data.players.push((
+            *sid,
+            PlayerData {
+                user_id: id,
+                name: name.to_owned(),
+                role: None,
+                joined: unix_secs(),
+                state: PlayerState::Waiting.as_str().to_owned(),
+                presence: Presence::Offline { last_seen: None },
+            },
+        ));
+        data.log_event(
+            sid,
+            GameEvent::Joined {
+                user_id: id,
+                name: name.to_owned(),
+            },
+        );
+        Ok(id)
+    }
+
+    fn get_players(&self, sid: &SessionID) -> Result<Vec<PlayerData>, DbError> {
+        Ok(self
+            .data()
+            .players
+            .iter()
+            .filter(|(player_sid, _)| player_sid == sid)
+            .map(|(_, player)| player.clone())
+            .collect())
+    }
+
+    fn get_player(&self, sid: &SessionID, user_id: u32) -> Result<PlayerData, DbError> {
+        self.data()
+            .player_mut(sid, user_id)
+            .map(|player| player.clone())
+    }
+
+    fn apply_event(&self, sid: &SessionID, event: GameEvent) -> Result<LoggedEvent, DbError> {
+        let mut data = self.data();
+
+        match &event {
+            GameEvent::Joined { .. } => {
+                return Err(DbError::Conflict(
+                    "Players join through maybe_add_player".into(),
+                ))
+            }
+            GameEvent::RoleAssigned { user_id, role } => {
+                let player = data.player_mut(sid, *user_id)?;
+                player.role = Some(role.clone());
+                if player.state == PlayerState::Waiting.as_str() {
+                    player.state = PlayerState::Alive.as_str().to_owned();
+                }
+            }
+            GameEvent::PhaseChanged { phase } => data.session_mut(sid)?.phase = *phase,
+            GameEvent::Won { .. } => data.session_mut(sid)?.phase = GamePhase::Ended,
+            GameEvent::Died { user_id, .. } => {
+                data.player_mut(sid, *user_id)?.state = PlayerState::Dead.as_str().to_owned()
+            }
+            GameEvent::StateChanged { user_id, state } => {
+                data.player_mut(sid, *user_id)?.state = state.as_str().to_owned()
+            }
+            GameEvent::VoteCast { .. } | GameEvent::NightAction { .. } => {
+                data.session_mut(sid)?;
+            }
+        }
+
+        let logged = data.log_event(sid, event);
+
+        if let GameEvent::PhaseChanged { .. } | GameEvent::Won { .. } = logged.event {
+            let created = data.session_mut(sid)?.created;
+            let created = created
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let events: Vec<LoggedEvent> = data
+                .events
+                .iter()
+                .filter(|(event_sid, _)| event_sid == sid)
+                .map(|(_, logged)| logged.clone())
+                .collect();
+            if let Some(game) = game_log::finished_game(sid, created, &events) {
+                data.archive_game(game);
+            }
+        }
+
+        Ok(logged)
+    }
+
+    fn get_events(&self, sid: &SessionID) -> Result<Vec<LoggedEvent>, DbError> {
+        Ok(self
+            .data()
+            .events
+            .iter()
+            .filter(|(event_sid, _)| event_sid == sid)
+            .map(|(_, logged)| logged.clone())
+            .collect())
+    }
+
+    fn get_archived_games(&self, player: Option<&str>) -> Result<Vec<ArchivedGameInfo>, DbError> {
+        let mut games: Vec<ArchivedGameInfo> = self
+            .data()
+            .archive
+            .iter()
+            .filter(|game| match player {
+                Some(name) => game.players.iter().any(|p| p.name == name),
+                None => true,
+            })
+            .map(ArchivedGameInfo::from)
+            .collect();
+        games.sort_by(|a, b| b.ended.cmp(&a.ended));
+        Ok(games)
+    }
+
+    fn backup(&self, _dst: &Path) -> Result<(), DbError> {
+        Err(DbError::Io(
+            "The in-memory storage can't be backed up".into(),
+        ))
+    }
+
+    fn cleanup(&self, cutoffs: &RetentionCutoffs, dry_run: bool) -> Result<CleanupReport, DbError> {
+        let mut data = self.data();
+
+        let idle: Vec<SessionID> = data
+            .sessions
+            .iter()
+            .filter(|(sid, s)| s.active && data.last_activity(sid, s) < cutoffs.idle_before)
+            .map(|(sid, _)| *sid)
+            .collect();
+        let inactive: Vec<SessionID> = data
+            .sessions
+            .iter()
+            .filter(|(sid, s)| !s.active || idle.contains(sid))
+            .map(|(sid, _)| *sid)
+            .collect();
+        let expired: Vec<SessionID> = inactive
+            .iter()
+            .filter(|sid| data.last_activity(sid, &data.sessions[*sid]) < cutoffs.closed_before)
+            .copied()
+            .collect();
+
+        let expired_msg = |sid: &SessionID, msg: &ChatMessage| -> bool {
+            expired.contains(sid) || (inactive.contains(sid) && msg.send_date < cutoffs.chat_before)
+        };
+
+        let report = CleanupReport {
+            dry_run,
+            ran_at: unix_secs(),
+            sessions_deactivated: idle.len() as u32,
+            sessions_deleted: expired.len() as u32,
+            players_deleted: data
+                .players
+                .iter()
+                .filter(|(sid, _)| expired.contains(sid))
+                .count() as u32,
+            messages_deleted: data
+                .chat
+                .iter()
+                .filter(|(sid, msg)| expired_msg(sid, msg))
+                .count() as u32,
+            events_deleted: data
+                .events
+                .iter()
+                .filter(|(sid, _)| expired.contains(sid))
+                .count() as u32,
+            deactivated: idle.clone(),
+            deleted: expired.clone(),
+        };
+
+        if !dry_run {
+            for sid in &idle {
+                data.session_mut(sid)?.active = false;
+            }
+            data.players.retain(|(sid, _)| !expired.contains(sid));
+            data.chat.retain(|(sid, msg)| !expired_msg(sid, msg));
+            data.events.retain(|(sid, _)| !expired.contains(sid));
+            data.sessions.retain(|sid, _| !expired.contains(sid));
+        }
+        Ok(report)
+    }
+
+    fn get_archived_game(&self, game_id: i64) -> Result<ArchivedGame, DbError> {
+        self.data()
+            .archive
+            .iter()
+            .find(|game| game.game_id == game_id)
+            .cloned()
+            .ok_or_else(|| DbError::NotFound("Game not found".into()))
+    }
+
+    fn add_chat_message(
+        &self,
+        sid: &SessionID,
+        sender: Option<u32>,
+        channel: ChatChannel,
+        to: Option<u32>,
+        message: &str,
+    ) -> Result<ChatMessage, DbError> {
+        let mut data = self.data();
+        data.session_mut(sid)?;
+        let name_of = |user_id: u32| {
+            data.players
+                .iter()
+                .find(|(_, player)| player.user_id == user_id)
+                .map(|(_, player)| player.name.clone())
+        };
+
+        let msg = ChatMessage {
+            message_id: data.last_message_id + 1,
+            sender,
+            sender_name: match sender {
+                Some(user_id) => name_of(user_id).unwrap_or_default(),
+                None => ADMIN_SENDER_NAME.to_owned(),
+            },
+            channel,
+            to,
+            to_name: to.and_then(name_of),
+            message: message.to_owned(),
+            send_date: unix_secs(),
+        };
+
+        data.last_message_id = msg.message_id;
+        data.chat.push((*sid, msg.clone()));
+        Ok(msg)
+    }
+
+    fn get_chat_messages(
+        &self,
+        sid: &SessionID,
+        query: &ChatQuery,
+    ) -> Result<Vec<ChatMessage>, DbError> {
+        let data = self.data();
+        // the chat is kept in message_id order
+        let matching = data
+            .chat
+            .iter()
+            .filter(|(msg_sid, msg)| msg_sid == sid && query.matches(msg))
+            .map(|(_, msg)| msg.clone());
+        let limit = query.limit.map_or(usize::MAX, |limit| limit as usize);
+
+        if query.after.is_some() {
+            return Ok(matching.take(limit).collect());
+        }
+        let mut newest: Vec<ChatMessage> = matching.rev().take(limit).collect();
+        newest.reverse();
+        Ok(newest)
+    }
+}
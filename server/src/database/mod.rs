@@ -0,0 +1,265 @@
+use crate::api::auth::SessionID;
+use crate::api::net_types::{
+    ArchivedGame, ArchivedGameInfo, ChatChannel, ChatMessage, CleanupReport, GameEvent, GamePhase,
+    LoggedEvent, PlayerData, SessionSettings,
+};
+use crate::SessionData;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+
+#[cfg(test)]
+mod conformance;
+mod error;
+mod memory;
+mod pool;
+mod sqlite;
+
+pub use error::DbError;
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+/// shown as sender_name of admin messages
+const ADMIN_SENDER_NAME: &str = "Spielleitung";
+
+/// a collision of 32 bit random ids is rare, several in a row mean something else is wrong
+const USER_ID_ATTEMPTS: usize = 8;
+
+/// unix secs, data with its last activity before these gets cleaned up.
+/// The last activity of a session is its newest message, event or join
+pub struct RetentionCutoffs {
+    /// active sessions get deactivated
+    pub idle_before: u64,
+    /// inactive sessions get deleted with their players, chat and events
+    pub closed_before: u64,
+    /// messages of inactive sessions get deleted
+    pub chat_before: u64,
+}
+
+/// a page of the chat history, everything of the session if nothing is set
+#[derive(Default)]
+pub struct ChatQuery {
+    /// only messages with a smaller message_id
+    pub before: Option<i64>,
+    /// only messages with a larger message_id, then the page holds the oldest of them.
+    /// Otherwise it holds the newest ones
+    pub after: Option<i64>,
+    /// only these channels, all of them if None
+    pub channels: Option<Vec<ChatChannel>>,
+    /// only the whispers sent or received by this player
+    pub whispers_of: Option<u32>,
+    /// size of the page, messages are always returned in order
+    pub limit: Option<u32>,
+}
+
+impl ChatQuery {
+    /// the conditions without the page, the storage applies limit and order itself
+    pub fn matches(&self, msg: &ChatMessage) -> bool {
+        self.before.map_or(true, |id| msg.message_id < id)
+            && self.after.map_or(true, |id| msg.message_id > id)
+            && self
+                .channels
+                .as_ref()
+                .map_or(true, |channels| channels.contains(&msg.channel))
+            && (msg.channel != ChatChannel::Whisper
+                || self
+                    .whispers_of
+                    .map_or(true, |id| msg.sender == Some(id) || msg.to == Some(id)))
+    }
+}
+
+/// what the admin stats need from the storage
+pub struct StorageStats {
+    pub sessions_active: u32,
+    /// players of the active sessions
+    pub players_active: u32,
+    /// distinct user_ids of all sessions
+    pub unique_users: u32,
+    /// active sessions at day or night
+    pub games_in_progress: u32,
+    /// chat messages sent since the given time
+    pub messages_since: u32,
+}
+
+/// order of the session list, ties are broken by the id
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionSort {
+    Newest,
+    Oldest,
+    /// most players first
+    Players,
+}
+
+impl SessionSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionSort::Newest => "newest",
+            SessionSort::Oldest => "oldest",
+            SessionSort::Players => "players",
+        }
+    }
+
+    /// the value sessions are ordered by
+    pub fn key(&self, session: &SessionData) -> u64 {
+        match self {
+            SessionSort::Newest | SessionSort::Oldest => session.created_secs(),
+            SessionSort::Players => session.player_count as u64,
+        }
+    }
+
+    /// whether session comes after the cursor in this order
+    pub fn is_after(&self, session: &SessionData, cursor: &SessionCursor) -> bool {
+        let position = (self.key(session), session.id.as_str());
+        let cursor = (cursor.key, cursor.id.as_str());
+        match self {
+            SessionSort::Oldest => position > cursor,
+            SessionSort::Newest | SessionSort::Players => position < cursor,
+        }
+    }
+}
+
+impl TryFrom<&str> for SessionSort {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "newest" => Ok(SessionSort::Newest),
+            "oldest" => Ok(SessionSort::Oldest),
+            "players" => Ok(SessionSort::Players),
+            _ => Err("Unknown sort, use newest, oldest or players"),
+        }
+    }
+}
+
+/// the last session of a page, the next page starts after it.
+/// Sent to clients as an opaque string like newest.1600000000.AB12CD34
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SessionCursor {
+    pub sort: SessionSort,
+    pub key: u64,
+    pub id: SessionID,
+}
+
+impl SessionCursor {
+    pub fn of(sort: SessionSort, session: &SessionData) -> Self {
+        SessionCursor {
+            sort,
+            key: sort.key(session),
+            id: session.id,
+        }
+    }
+}
+
+impl fmt::Display for SessionCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}",
+            self.sort.as_str(),
+            self.key,
+            self.id.as_str()
+        )
+    }
+}
+
+impl TryFrom<&str> for SessionCursor {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut parts = value.splitn(3, '.');
+        let sort = SessionSort::try_from(parts.next().unwrap_or(""))?;
+        let key = parts
+            .next()
+            .and_then(|key| key.parse().ok())
+            .ok_or("Invalid cursor")?;
+        let id = SessionID::try_from(parts.next().unwrap_or("")).map_err(|_| "Invalid cursor")?;
+        Ok(SessionCursor { sort, key, id })
+    }
+}
+
+/// filters and page of the session list
+pub struct SessionQuery {
+    pub active: Option<bool>,
+    /// only sessions created after this, unix secs
+    pub created_after: Option<u64>,
+    pub sort: SessionSort,
+    /// only sessions after this one, its sort has to match
+    pub cursor: Option<SessionCursor>,
+    pub limit: u32,
+}
+
+/// everything the server persists. Routes only know this trait,
+/// the backend is picked in main
+pub trait Storage: Send + Sync {
+    fn create_session(&self, sid: &SessionID) -> Result<(), DbError>;
+
+    fn get_session_data(&self, sid: &SessionID) -> Result<SessionData, DbError>;
+
+    /// at most limit sessions in the order of the query, with their player count
+    fn get_sessions(&self, query: &SessionQuery) -> Result<Vec<SessionData>, DbError>;
+
+    /// counts of the active sessions, messages are counted from messages_since (unix secs) on
+    fn get_stats(&self, messages_since: u64) -> Result<StorageStats, DbError>;
+
+    fn set_session_settings(
+        &self,
+        sid: &SessionID,
+        settings: &SessionSettings,
+    ) -> Result<(), DbError>;
+
+    /// adds the player if the following conditions are met:
+    /// 1) the session provided already exists
+    /// 2) there's no player with the same name in that session
+    /// 3) TODO: The player is not blacklisted by IP / Name
+    ///
+    /// Returns the player ID if created, the Joined event is logged with it
+    fn maybe_add_player(&self, name: &str, sid: &SessionID) -> Result<u32, DbError>;
+
+    fn get_players(&self, sid: &SessionID) -> Result<Vec<PlayerData>, DbError>;
+
+    /// the current data of a player, the token may contain an outdated role or state
+    fn get_player(&self, sid: &SessionID, user_id: u32) -> Result<PlayerData, DbError>;
+
+    /// the only way to change phase, roles or player states:
+    /// logs the event and updates the session and players in one step.
+    /// An event ending the game also writes its archive record
+    fn apply_event(&self, sid: &SessionID, event: GameEvent) -> Result<LoggedEvent, DbError>;
+
+    /// the game log of the session in order
+    fn get_events(&self, sid: &SessionID) -> Result<Vec<LoggedEvent>, DbError>;
+
+    /// finished games, newest first, only those the named player took part in if set
+    fn get_archived_games(&self, player: Option<&str>) -> Result<Vec<ArchivedGameInfo>, DbError>;
+
+    fn get_archived_game(&self, game_id: i64) -> Result<ArchivedGame, DbError>;
+
+    /// writes a consistent copy of everything to dst while the server keeps running
+    fn backup(&self, dst: &Path) -> Result<(), DbError>;
+
+    /// removes stale data, a dry run only counts what would be removed.
+    /// The archive is never touched
+    fn cleanup(&self, cutoffs: &RetentionCutoffs, dry_run: bool) -> Result<CleanupReport, DbError>;
+
+    /// sender is the user_id of a player, or None for admins, to only set for whispers.
+    /// The message has to be validated and escaped already
+    fn add_chat_message(
+        &self,
+        sid: &SessionID,
+        sender: Option<u32>,
+        channel: ChatChannel,
+        to: Option<u32>,
+        message: &str,
+    ) -> Result<ChatMessage, DbError>;
+
+    /// messages of the session in order, see ChatQuery
+    fn get_chat_messages(
+        &self,
+        sid: &SessionID,
+        query: &ChatQuery,
+    ) -> Result<Vec<ChatMessage>, DbError>;
+}
+
+/// unknown phases are treated like a game that hasn't started yet
+pub fn phase_from_str(phase: &str) -> GamePhase {
+    GamePhase::try_from(phase).unwrap_or(GamePhase::Lobby)
+}
@@ -0,0 +1,134 @@
+use super::DbError;
+use log::{error, warn};
+use rusqlite::{Connection, OpenFlags};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// how long a connection waits for the write lock of another one
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct PoolState {
+    idle: Vec<Connection>,
+    /// idle and checked out connections
+    open: usize,
+}
+
+/// a fixed number of connections to the same file, opened on demand.
+/// Connections are taken out of the pool while in use, so no lock is held
+/// during a query and a panicking handler can't poison anything shared
+pub struct Pool {
+    path: PathBuf,
+    flags: OpenFlags,
+    max_size: usize,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+impl Pool {
+    pub fn new(path: PathBuf, flags: OpenFlags, max_size: usize) -> Pool {
+        Pool {
+            path,
+            flags,
+            max_size,
+            state: Mutex::new(PoolState {
+                idle: Vec::with_capacity(max_size),
+                open: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    /// the state is only changed in small sections that can't panic
+    fn lock_state(&self) -> MutexGuard<PoolState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// waits until a connection is idle or a new one may be opened
+    pub fn get(&self) -> Result<PooledConnection, DbError> {
+        let mut state = self.lock_state();
+        loop {
+            if let Some(conn) = state.idle.pop() {
+                return Ok(PooledConnection {
+                    pool: self,
+                    conn: Some(conn),
+                });
+            }
+
+            if state.open < self.max_size {
+                state.open += 1;
+                drop(state);
+                return match self.open_connection() {
+                    Ok(conn) => Ok(PooledConnection {
+                        pool: self,
+                        conn: Some(conn),
+                    }),
+                    Err(e) => {
+                        error!(target: "database", "Failed to open connection: {}", e);
+                        self.release(None);
+                        Err(e)
+                    }
+                };
+            }
+
+            state = self
+                .available
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn open_connection(&self) -> Result<Connection, DbError> {
+        let conn = Connection::open_with_flags(&self.path, self.flags)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
+
+    /// puts the connection back, None frees its slot for a new one
+    fn release(&self, conn: Option<Connection>) {
+        let mut state = self.lock_state();
+        match conn {
+            Some(conn) => state.idle.push(conn),
+            None => state.open -= 1,
+        }
+        drop(state);
+        self.available.notify_one();
+    }
+}
+
+/// returns the connection to the pool when dropped
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    conn: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        let conn = self.conn.take();
+        // a connection left mid transaction or mid panic is in an unknown state,
+        // it gets closed and replaced by a fresh one on the next get
+        let conn = conn.filter(|conn| {
+            let healthy = !std::thread::panicking() && conn.is_autocommit();
+            if !healthy {
+                warn!(target: "database", "Replacing connection in unknown state");
+            }
+            healthy
+        });
+        self.pool.release(conn);
+    }
+}
@@ -0,0 +1,1056 @@
+use super::pool::{Pool, PooledConnection};
+use super::{
+    phase_from_str, ChatQuery, DbError, RetentionCutoffs, SessionQuery, SessionSort, Storage,
+    StorageStats, ADMIN_SENDER_NAME, USER_ID_ATTEMPTS,
+};
+use crate::api::auth::player_token::PlayerState;
+use crate::api::auth::SessionID;
+use crate::api::net_types::{
+    ArchivedGame, ArchivedGameInfo, ChatChannel, ChatMessage, CleanupReport, GameEvent, GamePhase,
+    LoggedEvent, PlayerData, PlayerSnapshot, Presence, SessionSettings,
+};
+use crate::game_log;
+use crate::SessionData;
+use log::{error, info, warn};
+use rusqlite::{
+    ffi, params, Connection, DatabaseName, OpenFlags, OptionalExtension, Row, NO_PARAMS,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+
+/// sqlite in WAL mode: readers don't block each other or the writer,
+/// all writes go through the single writer connection
+pub struct SqliteStorage {
+    writer: Pool,
+    readers: Pool,
+}
+
+/// as many as rocket has workers, so reads never queue
+const READER_COUNT: usize = 4;
+
+/// unix secs of the newest message, event or join of a session, used in the cleanup
+const LAST_ACTIVITY: &str = "MAX(sessions.created, \
+    IFNULL((SELECT MAX(send_date) FROM chat WHERE chat.session_id = sessions.id), 0), \
+    IFNULL((SELECT MAX(joined) FROM users WHERE users.session_id = sessions.id), 0), \
+    IFNULL((SELECT MAX(created) FROM game_events WHERE game_events.session_id = sessions.id), 0))";
+
+/// numbered schema migrations compiled into the binary, applied in order.
+/// Never change a released one, add a new one instead
+const MIGRATIONS: &[(u32, &str)] = &[
+    (1, include_str!("../../migrations/0001_initial.sql")),
+    (2, include_str!("../../migrations/0002_chat_channels.sql")),
+    (3, include_str!("../../migrations/0003_game_events.sql")),
+    (4, include_str!("../../migrations/0004_game_archive.sql")),
+    (5, include_str!("../../migrations/0005_session_list.sql")),
+    (6, include_str!("../../migrations/0006_chat_send_date.sql")),
+    (7, include_str!("../../migrations/0007_chat_session.sql")),
+    (8, include_str!("../../migrations/0008_player_names.sql")),
+];
+
+impl SqliteStorage {
+    /// creates the database if it doesn't exist and upgrades it to the latest schema
+    pub fn open(path: &Path) -> Result<SqliteStorage, DbError> {
+        let db = SqliteStorage {
+            writer: Pool::new(path.to_owned(), OpenFlags::default(), 1),
+            readers: Pool::new(
+                path.to_owned(),
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                READER_COUNT,
+            ),
+        };
+
+        {
+            let mut conn = db.writer.get()?;
+            // persisted in the file, the readers get it as well
+            let mode: String =
+                conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| row.get(0))?;
+            if !mode.eq_ignore_ascii_case("wal") {
+                warn!(target: "database", "Database is in journal mode {}, not WAL", mode);
+            }
+            SqliteStorage::migrate(&mut conn)?;
+            SqliteStorage::verify(&conn)?;
+        }
+        Ok(db)
+    }
+
+    /// a connection for queries that only read
+    fn read(&self) -> Result<PooledConnection, DbError> {
+        self.readers.get()
+    }
+
+    /// the only connection that may write, waits until it is free
+    fn write(&self) -> Result<PooledConnection, DbError> {
+        self.writer.get()
+    }
+
+    /// version of the newest migration this binary knows
+    pub fn latest_schema_version() -> u32 {
+        MIGRATIONS.last().map(|(version, _)| *version).unwrap_or(0)
+    }
+
+    /// applies all missing migrations in one transaction, so a failing one leaves
+    /// the database untouched. Refuses databases written by a newer binary
+    fn migrate(conn: &mut Connection) -> Result<(), DbError> {
+        let tx = conn.transaction()?;
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+            NO_PARAMS,
+        )?;
+
+        let current: u32 = tx
+            .query_row(
+                "SELECT MAX(version) FROM schema_version",
+                NO_PARAMS,
+                |row| row.get::<usize, Option<u32>>(0),
+            )?
+            .unwrap_or(0);
+        let latest = SqliteStorage::latest_schema_version();
+
+        if current > latest {
+            error!(
+                target: "database",
+                "Database has schema version {}, this binary only knows up to {}", current, latest
+            );
+            return Err(DbError::Conflict(format!(
+                "Database schema version {} is newer than supported version {}",
+                current, latest
+            )));
+        }
+
+        for (version, sql) in MIGRATIONS.iter().filter(|(version, _)| *version > current) {
+            info!(target: "database", "Applying migration {}", version);
+            tx.execute_batch(sql)
+                .map_err(|e| DbError::Corrupt(format!("Migration {} failed: {}", version, e)))?;
+            tx.execute(
+                "INSERT INTO schema_version (version) VALUES (?)",
+                params![version],
+            )?;
+        }
+
+        tx.commit()?;
+        info!(target: "database", "Schema is at version {}", latest);
+        Ok(())
+    }
+
+    /// checks if the connected database contains the needed tables and columns with
+    /// the right types. Reports every mismatch, not just the first one
+    fn verify(conn: &Connection) -> Result<(), DbError> {
+        let needed_tables = [
+            (
+                "sessions",
+                vec![
+                    ("id", "TEXT"),
+                    ("created", "INTEGER"),
+                    ("active", "INTEGER"),
+                    ("settings", "TEXT"),
+                    ("phase", "TEXT"),
+                ],
+            ),
+            (
+                "users",
+                vec![
+                    ("user_id", "INTEGER"),
+                    ("user_name", "TEXT"),
+                    ("session_id", "TEXT"),
+                    ("role", "TEXT"),
+                    ("joined", "INTEGER"),
+                    ("state", "TEXT"),
+                ],
+            ),
+            (
+                "chat",
+                vec![
+                    ("message_id", "INTEGER"),
+                    ("message", "TEXT"),
+                    ("send_date", "INTEGER"),
+                    ("sender", "TEXT"),
+                    ("to", "TEXT"),
+                    ("session_id", "TEXT"),
+                    ("channel", "TEXT"),
+                ],
+            ),
+            (
+                "game_events",
+                vec![
+                    ("seq", "INTEGER"),
+                    ("session_id", "TEXT"),
+                    ("created", "INTEGER"),
+                    ("kind", "TEXT"),
+                    ("data", "TEXT"),
+                ],
+            ),
+            (
+                "archived_games",
+                vec![
+                    ("game_id", "INTEGER"),
+                    ("session_id", "TEXT"),
+                    ("started", "INTEGER"),
+                    ("ended", "INTEGER"),
+                    ("winner", "TEXT"),
+                    ("rounds", "INTEGER"),
+                ],
+            ),
+            (
+                "archived_players",
+                vec![
+                    ("game_id", "INTEGER"),
+                    ("user_id", "INTEGER"),
+                    ("user_name", "TEXT"),
+                    ("role", "TEXT"),
+                    ("state", "TEXT"),
+                    ("death_cause", "TEXT"),
+                    ("death_round", "INTEGER"),
+                ],
+            ),
+        ];
+
+        let mut mismatches = Vec::new();
+
+        for (req_table, req_cols) in &needed_tables {
+            // table_info has no rows for missing tables; pragmas can't take parameters
+            let mut col_check = conn.prepare(&format!("PRAGMA table_info(\"{}\")", req_table))?;
+            let columns: HashMap<String, String> = col_check
+                .query_map(NO_PARAMS, |row| Ok((row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?;
+
+            if columns.is_empty() {
+                mismatches.push(format!("missing table {}", req_table));
+                continue;
+            }
+
+            for (req_col, req_type) in req_cols {
+                match columns.get(*req_col) {
+                    None => mismatches.push(format!("missing column {}.{}", req_table, req_col)),
+                    Some(col_type) if !col_type.eq_ignore_ascii_case(req_type) => {
+                        mismatches.push(format!(
+                            "column {}.{} has type {}, expected {}",
+                            req_table, req_col, col_type, req_type
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        for mismatch in &mismatches {
+            error!(target: "database", "Schema mismatch: {}", mismatch);
+        }
+        Err(DbError::Corrupt(format!(
+            "Invalid schema: {}",
+            mismatches.join(", ")
+        )))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_session(&self, sid: &SessionID) -> Result<(), DbError> {
+        let created = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as i64;
+        self.write()?.execute(
+            "INSERT INTO sessions (id, created, active) VALUES (?, ?, 1)",
+            params![sid.as_str(), created],
+        )?;
+        Ok(())
+    }
+
+    fn get_session_data(&self, sid: &SessionID) -> Result<SessionData, DbError> {
+        use std::time;
+
+        self.read()?
+            .query_row(
+                "SELECT created, active, settings, phase, \
+                (SELECT COUNT(*) FROM users WHERE users.session_id = sessions.id) \
+                FROM sessions WHERE id = ?",
+                &[sid.as_str()],
+                |row| {
+                    Ok(SessionData {
+                        id: *sid,
+                        created: time::UNIX_EPOCH
+                            + time::Duration::from_secs(row.get::<usize, i64>(0)? as u64),
+                        active: row.get(1)?,
+                        settings: row.get(2)?,
+                        phase: phase_from_str(&row.get::<usize, String>(3)?),
+                        player_count: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?
+            .ok_or_else(|| DbError::NotFound("Session doesn't exist".into()))
+    }
+
+    fn set_session_settings(
+        &self,
+        sid: &SessionID,
+        settings: &SessionSettings,
+    ) -> Result<(), DbError> {
+        let json = serde_json::to_string(settings)?;
+        match self.write()?.execute(
+            "UPDATE sessions SET settings = ? WHERE id = ?",
+            &[json.as_str(), sid.as_str()],
+        )? {
+            0 => Err(DbError::NotFound("Session doesn't exist".into())),
+            _ => Ok(()),
+        }
+    }
+
+    fn apply_event(&self, sid: &SessionID, event: GameEvent) -> Result<LoggedEvent, DbError> {
+        let mut conn = self.write()?;
+        let tx = conn.transaction()?;
+
+        // update the tables holding the latest state, 0 rows means unknown player or session
+        let changed = match &event {
+            GameEvent::Joined { .. } => {
+                return Err(DbError::Conflict(
+                    "Players join through maybe_add_player".into(),
+                ))
+            }
+            GameEvent::RoleAssigned { user_id, role } => tx.execute(
+                "UPDATE users SET role = ?, state = CASE state WHEN 'waiting' THEN 'alive' ELSE \
+                state END WHERE session_id = ? AND user_id = ?",
+                params![role, sid.as_str(), *user_id as i64],
+            ),
+            GameEvent::PhaseChanged { phase } => tx.execute(
+                "UPDATE sessions SET phase = ? WHERE id = ?",
+                &[phase.as_str(), sid.as_str()],
+            ),
+            GameEvent::Won { .. } => tx.execute(
+                "UPDATE sessions SET phase = ? WHERE id = ?",
+                &[GamePhase::Ended.as_str(), sid.as_str()],
+            ),
+            GameEvent::Died { user_id, .. } => tx.execute(
+                "UPDATE users SET state = ? WHERE session_id = ? AND user_id = ?",
+                params![PlayerState::Dead.as_str(), sid.as_str(), *user_id as i64],
+            ),
+            GameEvent::StateChanged { user_id, state } => tx.execute(
+                "UPDATE users SET state = ? WHERE session_id = ? AND user_id = ?",
+                params![state.as_str(), sid.as_str(), *user_id as i64],
+            ),
+            GameEvent::VoteCast { .. } | GameEvent::NightAction { .. } => tx
+                .query_row(
+                    "SELECT COUNT(*) FROM sessions WHERE id = ?",
+                    &[sid.as_str()],
+                    |row| row.get::<usize, i64>(0),
+                )
+                .map(|count| count as usize),
+        }?;
+
+        if changed == 0 {
+            return Err(DbError::NotFound(match event {
+                GameEvent::PhaseChanged { .. }
+                | GameEvent::Won { .. }
+                | GameEvent::VoteCast { .. }
+                | GameEvent::NightAction { .. } => "Session doesn't exist".into(),
+                _ => "Player not found".into(),
+            }));
+        }
+
+        let logged = insert_event(&tx, sid, event)?;
+
+        if let GameEvent::PhaseChanged { .. } | GameEvent::Won { .. } = logged.event {
+            let created: i64 = tx.query_row(
+                "SELECT created FROM sessions WHERE id = ?",
+                &[sid.as_str()],
+                |row| row.get(0),
+            )?;
+            let events = load_events(&tx, sid)?;
+            if let Some(game) = game_log::finished_game(sid, created as u64, &events) {
+                let game_id = archive_game(&tx, &game)?;
+                info!(target: "database", "Archived game {} of {}", game_id, sid);
+            }
+        }
+
+        tx.commit()?;
+        Ok(logged)
+    }
+
+    fn get_events(&self, sid: &SessionID) -> Result<Vec<LoggedEvent>, DbError> {
+        let conn = self.read()?;
+        load_events(&conn, sid)
+    }
+
+    fn get_archived_games(&self, player: Option<&str>) -> Result<Vec<ArchivedGameInfo>, DbError> {
+        let conn = self.read()?;
+        let mut stmt = conn.prepare(
+            "SELECT game_id, session_id, started, ended, winner, rounds, \
+            (SELECT COUNT(*) FROM archived_players p WHERE p.game_id = g.game_id) \
+            FROM archived_games g WHERE ?1 IS NULL OR EXISTS (SELECT 1 FROM archived_players \
+            p WHERE p.game_id = g.game_id AND p.user_name = ?1) ORDER BY ended DESC",
+        )?;
+
+        let games = stmt
+            .query_map(&[player], |row| {
+                Ok(ArchivedGameInfo {
+                    game_id: row.get(0)?,
+                    session_id: row.get(1)?,
+                    started: row.get::<usize, i64>(2)? as u64,
+                    ended: row.get::<usize, i64>(3)? as u64,
+                    winner: row.get(4)?,
+                    rounds: row.get(5)?,
+                    player_count: row.get(6)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(games)
+    }
+
+    fn backup(&self, dst: &Path) -> Result<(), DbError> {
+        // sqlite's online backup, the writer can go on meanwhile
+        self.read()?
+            .backup(DatabaseName::Main, dst, None)
+            .map_err(DbError::from)
+    }
+
+    fn cleanup(&self, cutoffs: &RetentionCutoffs, dry_run: bool) -> Result<CleanupReport, DbError> {
+        let mut conn = self.write()?;
+        // a dry run does the same work and rolls it back, so the counts are exact
+        let tx = conn.transaction()?;
+        let mut report = CleanupReport {
+            dry_run,
+            ran_at: std::time::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+            ..Default::default()
+        };
+
+        report.deactivated = session_ids(
+            &tx,
+            &format!(
+                "SELECT id FROM sessions WHERE active = 1 AND {} < ?",
+                LAST_ACTIVITY
+            ),
+            cutoffs.idle_before,
+        )?;
+        for sid in &report.deactivated {
+            tx.execute(
+                "UPDATE sessions SET active = 0 WHERE id = ?",
+                &[sid.as_str()],
+            )?;
+        }
+        report.sessions_deactivated = report.deactivated.len() as u32;
+
+        report.deleted = session_ids(
+            &tx,
+            &format!(
+                "SELECT id FROM sessions WHERE active = 0 AND {} < ?",
+                LAST_ACTIVITY
+            ),
+            cutoffs.closed_before,
+        )?;
+        for sid in &report.deleted {
+            let delete = |sql: &str| tx.execute(sql, &[sid.as_str()]);
+            report.players_deleted += delete("DELETE FROM users WHERE session_id = ?")? as u32;
+            report.messages_deleted += delete("DELETE FROM chat WHERE session_id = ?")? as u32;
+            report.events_deleted += delete("DELETE FROM game_events WHERE session_id = ?")? as u32;
+            report.sessions_deleted += delete("DELETE FROM sessions WHERE id = ?")? as u32;
+        }
+
+        report.messages_deleted += tx.execute(
+            "DELETE FROM chat WHERE send_date < ? AND session_id IN \
+            (SELECT id FROM sessions WHERE active = 0)",
+            [cutoffs.chat_before as i64],
+        )? as u32;
+
+        if dry_run {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+        }
+        Ok(report)
+    }
+
+    fn get_archived_game(&self, game_id: i64) -> Result<ArchivedGame, DbError> {
+        let conn = self.read()?;
+        let mut game = conn
+            .query_row(
+                "SELECT session_id, started, ended, winner, rounds FROM archived_games \
+                WHERE game_id = ?",
+                [game_id],
+                |row| {
+                    Ok(ArchivedGame {
+                        game_id,
+                        session_id: row.get(0)?,
+                        started: row.get::<usize, i64>(1)? as u64,
+                        ended: row.get::<usize, i64>(2)? as u64,
+                        winner: row.get(3)?,
+                        rounds: row.get(4)?,
+                        players: Vec::new(),
+                    })
+                },
+            )
+            .optional()?
+            .ok_or_else(|| DbError::NotFound("Game not found".into()))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT user_id, user_name, role, state, death_cause, death_round \
+            FROM archived_players WHERE game_id = ? ORDER BY rowid",
+        )?;
+        game.players = stmt
+            .query_map([game_id], |row| {
+                Ok(PlayerSnapshot {
+                    user_id: row.get(0)?,
+                    name: row.get(1)?,
+                    role: row.get(2)?,
+                    state: PlayerState::try_from(row.get::<usize, String>(3)?.as_str())
+                        .map_err(|e| conversion_error(3, e))?,
+                    death_cause: row.get(4)?,
+                    death_round: row.get(5)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(game)
+    }
+
+    fn get_sessions(&self, query: &SessionQuery) -> Result<Vec<SessionData>, DbError> {
+        let (key, order, after) = match query.sort {
+            SessionSort::Newest => ("created", "DESC", "<"),
+            SessionSort::Oldest => ("created", "ASC", ">"),
+            SessionSort::Players => ("player_count", "DESC", "<"),
+        };
+        // players are counted in the same query, the cursor compares (key, id)
+        let sql = format!(
+            "SELECT id, created, active, settings, phase, player_count FROM (\
+            SELECT s.id, s.created, s.active, s.settings, s.phase, COUNT(u.user_id) AS player_count \
+            FROM sessions s LEFT JOIN users u ON u.session_id = s.id \
+            WHERE (?1 IS NULL OR s.active = ?1) AND (?2 IS NULL OR s.created > ?2) \
+            GROUP BY s.id) \
+            WHERE ?3 IS NULL OR {0} {2} ?3 OR ({0} = ?3 AND id {2} ?4) \
+            ORDER BY {0} {1}, id {1} LIMIT ?5",
+            key, order, after
+        );
+
+        let conn = self.read()?;
+        let mut prep = conn.prepare(&sql)?;
+        let cursor = query.cursor.as_ref();
+        let sessions = prep
+            .query_map(
+                params![
+                    query.active,
+                    query.created_after.map(|secs| secs as i64),
+                    cursor.map(|c| c.key as i64),
+                    cursor.map(|c| c.id.as_str()),
+                    query.limit,
+                ],
+                |row| {
+                    let id: String = row.get(0)?;
+                    Ok(SessionData {
+                        id: SessionID::try_from(id.as_str()).map_err(|e| conversion_error(0, e))?,
+                        created: std::time::UNIX_EPOCH
+                            + std::time::Duration::from_secs(row.get::<usize, i64>(1)? as u64),
+                        active: row.get(2)?,
+                        settings: row.get(3)?,
+                        phase: phase_from_str(&row.get::<usize, String>(4)?),
+                        player_count: row.get(5)?,
+                    })
+                },
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok(sessions)
+    }
+
+    fn get_stats(&self, messages_since: u64) -> Result<StorageStats, DbError> {
+        let stats = self.read()?.query_row(
+            "SELECT (SELECT COUNT(*) FROM sessions WHERE active = 1), \
+            (SELECT COUNT(*) FROM users JOIN sessions ON sessions.id = users.session_id \
+            WHERE sessions.active = 1), \
+            (SELECT COUNT(DISTINCT user_id) FROM users), \
+            (SELECT COUNT(*) FROM sessions WHERE active = 1 AND phase IN ('day', 'night')), \
+            (SELECT COUNT(*) FROM chat WHERE send_date >= ?)",
+            [messages_since as i64],
+            |row| {
+                Ok(StorageStats {
+                    sessions_active: row.get(0)?,
+                    players_active: row.get(1)?,
+                    unique_users: row.get(2)?,
+                    games_in_progress: row.get(3)?,
+                    messages_since: row.get(4)?,
+                })
+            },
+        )?;
+        Ok(stats)
+    }
+
+    /// adds the player if the following conditions are met:
+    /// 1) the session provided already exists
+    /// 2) there's no player with the same name in that session
+    /// 3) TODO: The player is not blacklisted by IP / Name
+    ///
+    /// Returns the player ID if created
+    fn maybe_add_player(&self, name: &str, sid: &SessionID) -> Result<u32, DbError> {
+        let mut conn = self.write()?;
+        let conn = conn.transaction()?;
+
+        // 1) check if session exists
+        let session_check: Option<bool> = conn
+            .query_row(
+                "SELECT active FROM sessions WHERE id = ?",
+                &[sid.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match session_check {
+            Some(true) => {}
+            Some(false) => {
+                error!("Tried to add player to inactive session");
+                return Err(DbError::Conflict("Session inactive".into()));
+            }
+            None => {
+                error!("Session doesn't exist");
+                return Err(DbError::NotFound("Session doesn't exist".into()));
+            }
+        }
+
+        // 2) no player with same name in the session
+
+        let name_taken = conn
+            .query_row(
+                "SELECT user_id FROM users WHERE user_name = ? AND session_id = ?",
+                &[name, sid.as_str()],
+                |_| Ok(true),
+            )
+            .optional()?
+            .is_some();
+        if name_taken {
+            error!("Two users with the same name tried to join TODO handle authorized rejoin");
+            return Err(DbError::Conflict("A user with the same name exists".into()));
+        }
+
+        let joined = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as i64;
+
+        // add player
+        let id = insert_player(&conn, name, sid, joined)?;
+
+        insert_event(
+            &conn,
+            sid,
+            GameEvent::Joined {
+                user_id: id,
+                name: name.to_owned(),
+            },
+        )?;
+        conn.commit()?;
+
+        Ok(id)
+    }
+
+    fn get_players(&self, sid: &SessionID) -> Result<Vec<PlayerData>, DbError> {
+        let conn = self.read()?;
+        let mut stmt = conn.prepare(
+            "SELECT user_id, user_name, role, joined, state FROM users WHERE session_id =\
+             ?",
+        )?;
+
+        let players = stmt
+            .query_map(&[sid.as_str()], player_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(players)
+    }
+
+    fn get_player(&self, sid: &SessionID, user_id: u32) -> Result<PlayerData, DbError> {
+        self.read()?
+            .query_row(
+                "SELECT user_id, user_name, role, joined, state FROM users WHERE session_id = ? \
+                AND user_id = ?",
+                params![sid.as_str(), user_id as i64],
+                player_from_row,
+            )
+            .optional()?
+            .ok_or_else(|| DbError::NotFound("Player not found".into()))
+    }
+
+    fn add_chat_message(
+        &self,
+        sid: &SessionID,
+        sender: Option<u32>,
+        channel: ChatChannel,
+        to: Option<u32>,
+        message: &str,
+    ) -> Result<ChatMessage, DbError> {
+        let conn = self.write()?;
+        let send_date = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs() as i64;
+        let sender_col = match sender {
+            Some(user_id) => user_id.to_string(),
+            None => ADMIN_SENDER.to_owned(),
+        };
+
+        // like the memory storage, messages of unknown sessions are refused
+        let inserted = conn.execute(
+            "INSERT INTO chat (message, send_date, sender, session_id, channel, \"to\") \
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6 WHERE EXISTS (SELECT 1 FROM sessions WHERE id = ?4)",
+            params![
+                message,
+                send_date,
+                sender_col,
+                sid.as_str(),
+                channel.as_str(),
+                to.map(|user_id| user_id.to_string())
+            ],
+        )?;
+        if inserted == 0 {
+            return Err(DbError::NotFound("Session doesn't exist".into()));
+        }
+
+        let message_id = conn.last_insert_rowid();
+        let msg = conn.query_row(
+            &[CHAT_SELECT, "WHERE chat.message_id = ?"].concat(),
+            [message_id],
+            chat_message_from_row,
+        )?;
+        Ok(msg)
+    }
+
+    fn get_chat_messages(
+        &self,
+        sid: &SessionID,
+        query: &ChatQuery,
+    ) -> Result<Vec<ChatMessage>, DbError> {
+        // the channel names are constants, so they can be part of the sql
+        let channels = match &query.channels {
+            Some(channels) => format!(
+                "AND chat.channel IN ({}) ",
+                channels
+                    .iter()
+                    .map(|channel| format!("'{}'", channel.as_str()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => String::new(),
+        };
+        // the page is taken from the end unless reading forward from after
+        let order = if query.after.is_some() { "ASC" } else { "DESC" };
+        let sql = format!(
+            "{}WHERE chat.session_id = ?1 AND (?2 IS NULL OR chat.message_id < ?2) AND \
+            (?3 IS NULL OR chat.message_id > ?3) {}AND (chat.channel != 'whisper' OR \
+            ?4 IS NULL OR chat.sender = ?4 OR chat.\"to\" = ?4) \
+            ORDER BY chat.message_id {} LIMIT ?5",
+            CHAT_SELECT, channels, order
+        );
+
+        let conn = self.read()?;
+        let mut stmt = conn.prepare(&sql)?;
+        let mut messages: Vec<ChatMessage> = stmt
+            .query_map(
+                params![
+                    sid.as_str(),
+                    query.before,
+                    query.after,
+                    // sender and recipient are stored as text
+                    query.whispers_of.map(|id| id.to_string()),
+                    // a negative limit is no limit
+                    query.limit.map_or(-1, i64::from),
+                ],
+                chat_message_from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        if query.after.is_none() {
+            messages.reverse();
+        }
+        Ok(messages)
+    }
+}
+
+/// value of chat.sender for messages sent by an admin, players are stored with their user_id
+const ADMIN_SENDER: &str = "admin";
+
+/// selects the columns read by chat_message_from_row
+const CHAT_SELECT: &str = "SELECT chat.message_id, chat.sender, users.user_name, chat.message, \
+    chat.send_date, chat.channel, chat.\"to\", recipients.user_name FROM chat \
+    LEFT JOIN users ON users.user_id = chat.sender \
+    LEFT JOIN users AS recipients ON recipients.user_id = chat.\"to\" ";
+
+/// inserts the player with a random user_id, the id is unguessable so players can't
+/// address others by counting. A taken id makes the INSERT itself fail, so there's
+/// no gap between check and write another join could use; then a new id is tried
+fn insert_player(
+    conn: &Connection,
+    name: &str,
+    sid: &SessionID,
+    joined: i64,
+) -> Result<u32, DbError> {
+    for _ in 0..USER_ID_ATTEMPTS {
+        let id = rand::random::<u32>();
+        match conn.execute(
+            "INSERT INTO users (user_id, user_name, session_id, joined, state) \
+            VALUES (?, ?, ?, ?, ?)",
+            params![
+                id as i64,
+                name,
+                sid.as_str(),
+                joined,
+                PlayerState::Waiting.as_str()
+            ],
+        ) {
+            Ok(_) => return Ok(id),
+            // only the failed statement is undone, the transaction goes on
+            Err(e) if is_user_id_collision(&e) => {
+                warn!(target: "database", "user_id {} is taken, trying another one", id)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(DbError::Conflict(format!(
+        "No free user_id found in {} tries",
+        USER_ID_ATTEMPTS
+    )))
+}
+
+/// the unique check of user_id failed, the one of user_name fails with another message
+fn is_user_id_collision(e: &rusqlite::Error) -> bool {
+    match e {
+        rusqlite::Error::SqliteFailure(err, Some(msg)) => {
+            err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE && msg.contains("users.user_id")
+        }
+        _ => false,
+    }
+}
+
+/// the game log of the session in order
+fn load_events(conn: &Connection, sid: &SessionID) -> Result<Vec<LoggedEvent>, DbError> {
+    let mut stmt = conn
+        .prepare("SELECT seq, created, data FROM game_events WHERE session_id = ? ORDER BY seq")?;
+
+    let rows = stmt.query_map(&[sid.as_str()], |row| {
+        Ok((
+            row.get::<usize, i64>(0)?,
+            row.get::<usize, i64>(1)?,
+            row.get::<usize, String>(2)?,
+        ))
+    })?;
+
+    let mut events = Vec::new();
+    for row in rows {
+        let (seq, created, data) = row?;
+        // a replay without the event would be wrong, so it fails as a whole
+        let event = serde_json::from_str(&data).map_err(|e| {
+            error!(target: "database", "Unreadable game event {}: {}", seq, e);
+            DbError::from(e)
+        })?;
+        events.push(LoggedEvent {
+            seq,
+            created: created as u64,
+            event,
+        });
+    }
+    Ok(events)
+}
+
+/// writes the game, or replaces the record of the same game if it was archived before,
+/// e.g. when the winner is set after the phase was ended
+fn archive_game(conn: &Connection, game: &ArchivedGame) -> Result<i64, DbError> {
+    let existing: Option<i64> = conn
+        .query_row(
+            "SELECT game_id FROM archived_games WHERE session_id = ? AND started = ?",
+            params![game.session_id, game.started as i64],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    let game_id = match existing {
+        Some(game_id) => {
+            conn.execute(
+                "UPDATE archived_games SET ended = ?, winner = ?, rounds = ? WHERE game_id = ?",
+                params![game.ended as i64, game.winner, game.rounds, game_id],
+            )?;
+            conn.execute("DELETE FROM archived_players WHERE game_id = ?", [game_id])?;
+            game_id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO archived_games (session_id, started, ended, winner, rounds) \
+                VALUES (?, ?, ?, ?, ?)",
+                params![
+                    game.session_id,
+                    game.started as i64,
+                    game.ended as i64,
+                    game.winner,
+                    game.rounds
+                ],
+            )?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    for player in &game.players {
+        conn.execute(
+            "INSERT INTO archived_players (game_id, user_id, user_name, role, state, \
+            death_cause, death_round) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                game_id,
+                player.user_id as i64,
+                player.name,
+                player.role,
+                player.state.as_str(),
+                player.death_cause,
+                player.death_round
+            ],
+        )?;
+    }
+
+    Ok(game_id)
+}
+
+/// appends to the game log, has to run in the transaction changing the state
+fn insert_event(
+    conn: &Connection,
+    sid: &SessionID,
+    event: GameEvent,
+) -> Result<LoggedEvent, DbError> {
+    let created = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let data = serde_json::to_string(&event)?;
+
+    conn.execute(
+        "INSERT INTO game_events (session_id, created, kind, data) VALUES (?, ?, ?, ?)",
+        params![sid.as_str(), created as i64, event.kind(), data],
+    )?;
+
+    Ok(LoggedEvent {
+        seq: conn.last_insert_rowid(),
+        created,
+        event,
+    })
+}
+
+/// ids of the sessions the query selects, it takes a unix secs cutoff as parameter
+fn session_ids(conn: &Connection, sql: &str, before: u64) -> Result<Vec<SessionID>, DbError> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([before as i64], |row| {
+        let id: String = row.get(0)?;
+        SessionID::try_from(id.as_str()).map_err(|e| conversion_error(0, e))
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// a text column that doesn't parse, reported as corrupt data
+fn conversion_error(column: usize, e: &str) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into())
+}
+
+fn chat_message_from_row(row: &Row) -> rusqlite::Result<ChatMessage> {
+    let sender: Option<u32> = row.get::<usize, String>(1)?.parse().ok();
+    let sender_name: Option<String> = row.get(2)?;
+
+    Ok(ChatMessage {
+        message_id: row.get(0)?,
+        sender,
+        sender_name: match sender {
+            Some(_) => sender_name.unwrap_or_default(),
+            None => ADMIN_SENDER_NAME.to_owned(),
+        },
+        message: row.get(3)?,
+        send_date: row.get::<usize, i64>(4)? as u64,
+        channel: ChatChannel::try_from(row.get::<usize, String>(5)?.as_str())
+            .map_err(|e| conversion_error(5, e))?,
+        to: row
+            .get::<usize, Option<String>>(6)?
+            .and_then(|to| to.parse().ok()),
+        to_name: row.get(7)?,
+    })
+}
+
+fn player_from_row(usr_row: &Row) -> rusqlite::Result<PlayerData> {
+    Ok(PlayerData {
+        user_id: usr_row.get(0)?,
+        name: usr_row.get(1)?,
+        role: usr_row.get(2)?,
+        joined: usr_row.get::<usize, i64>(3)? as u64,
+        state: usr_row.get(4)?,
+        // only known by the notifier
+        presence: Presence::Offline { last_seen: None },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the tables as the old hand-run v01.sql created them, before migrations existed
+    const V01_SCHEMA: &str = r#"
+        CREATE TABLE "chat" (
+            "message_id" INTEGER NOT NULL UNIQUE,
+            "message" TEXT,
+            "send_date" INTEGER NOT NULL,
+            "sender" TEXT NOT NULL,
+            "to" TEXT,
+            PRIMARY KEY("message_id")
+        );
+        CREATE TABLE "users" (
+            "user_id" INTEGER NOT NULL UNIQUE,
+            "user_name" TEXT NOT NULL,
+            "session_id" TEXT,
+            "role" TEXT,
+            "joined" INTEGER,
+            "state" TEXT,
+            PRIMARY KEY("user_name")
+        );
+        CREATE TABLE "sessions" (
+            "id" TEXT NOT NULL UNIQUE,
+            "created" INTEGER NOT NULL,
+            "active" INTEGER NOT NULL,
+            "settings" TEXT,
+            PRIMARY KEY("id")
+        );
+        INSERT INTO sessions (id, created, active) VALUES ('AB12CD34', 1600000000, 1);
+        INSERT INTO users VALUES (7, 'anna', 'AB12CD34', NULL, 1600000001, 'waiting');
+        INSERT INTO chat (message_id, message, send_date, sender) VALUES (1, 'hi', 1600000002, '7');
+    "#;
+
+    #[test]
+    fn fresh_database_gets_the_full_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        SqliteStorage::migrate(&mut conn).unwrap();
+        SqliteStorage::verify(&conn).unwrap();
+    }
+
+    #[test]
+    fn v01_database_is_upgraded_with_its_data() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(V01_SCHEMA).unwrap();
+
+        SqliteStorage::migrate(&mut conn).unwrap();
+        SqliteStorage::verify(&conn).unwrap();
+
+        let (message, channel): (String, String) = conn
+            .query_row(
+                "SELECT message, channel FROM chat WHERE message_id = 1",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(message, "hi");
+        assert!(ChatChannel::try_from(channel.as_str()).is_ok());
+
+        let phase: String = conn
+            .query_row("SELECT phase FROM sessions", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(phase, "lobby");
+
+        let name: String = conn
+            .query_row(
+                "SELECT user_name FROM users WHERE user_id = 7",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(name, "anna");
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(V01_SCHEMA).unwrap();
+        SqliteStorage::migrate(&mut conn).unwrap();
+        SqliteStorage::migrate(&mut conn).unwrap();
+
+        let version: u32 = conn
+            .query_row(
+                "SELECT MAX(version) FROM schema_version",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(version, SqliteStorage::latest_schema_version());
+        SqliteStorage::verify(&conn).unwrap();
+    }
+}
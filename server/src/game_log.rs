@@ -0,0 +1,115 @@
+use crate::api::auth::player_token::PlayerState;
+use crate::api::auth::SessionID;
+use crate::api::net_types::{
+    ArchivedGame, GameEvent, GamePhase, GameState, LoggedEvent, PlayerSnapshot,
+};
+
+/// rebuilds the state of a session by applying its events in order
+pub fn replay<'a>(events: impl IntoIterator<Item = &'a LoggedEvent>) -> GameState {
+    let mut state = GameState {
+        phase: GamePhase::Lobby,
+        started: None,
+        round: 0,
+        players: Vec::new(),
+        winner: None,
+    };
+
+    for logged in events {
+        apply(&mut state, logged);
+    }
+    state
+}
+
+/// the archive record if the last event ended the game (Won or phase Ended),
+/// None otherwise. The game_id is left 0 for the storage to assign
+pub fn finished_game(
+    sid: &SessionID,
+    session_created: u64,
+    events: &[LoggedEvent],
+) -> Option<ArchivedGame> {
+    let last = events.last()?;
+    let ends_game = match last.event {
+        GameEvent::Won { .. } => true,
+        GameEvent::PhaseChanged { phase } => phase == GamePhase::Ended,
+        _ => false,
+    };
+    if !ends_game {
+        return None;
+    }
+
+    let state = replay(events);
+    Some(ArchivedGame {
+        game_id: 0,
+        session_id: sid.as_str().to_owned(),
+        started: state.started.unwrap_or(session_created),
+        ended: last.created,
+        winner: state.winner,
+        rounds: state.round,
+        players: state.players,
+    })
+}
+
+fn apply(state: &mut GameState, logged: &LoggedEvent) {
+    let round = state.round;
+
+    match &logged.event {
+        GameEvent::Joined { user_id, name } => state.players.push(PlayerSnapshot {
+            user_id: *user_id,
+            name: name.clone(),
+            role: None,
+            state: PlayerState::Waiting,
+            death_cause: None,
+            death_round: None,
+        }),
+        GameEvent::RoleAssigned { user_id, role } => {
+            if let Some(player) = find_player(state, *user_id) {
+                player.role = Some(role.clone());
+                if player.state == PlayerState::Waiting {
+                    player.state = PlayerState::Alive;
+                }
+            }
+        }
+        GameEvent::PhaseChanged { phase } => {
+            // leaving the lobby or a finished game starts a new one in the same session
+            let running = |phase: GamePhase| phase == GamePhase::Day || phase == GamePhase::Night;
+            if !running(state.phase) && running(*phase) {
+                state.started = Some(logged.created);
+                state.round = 0;
+                state.winner = None;
+            }
+            if *phase == GamePhase::Night {
+                state.round += 1;
+            }
+            state.phase = *phase;
+        }
+        // don't change the state, only kept for the record
+        GameEvent::VoteCast { .. } | GameEvent::NightAction { .. } => {}
+        GameEvent::Died { user_id, cause } => {
+            if let Some(player) = find_player(state, *user_id) {
+                player.state = PlayerState::Dead;
+                player.death_cause = cause.clone();
+                player.death_round = Some(round);
+            }
+        }
+        GameEvent::StateChanged {
+            user_id,
+            state: player_state,
+        } => {
+            if let Some(player) = find_player(state, *user_id) {
+                player.state = *player_state;
+                if *player_state != PlayerState::Dead {
+                    player.death_cause = None;
+                    player.death_round = None;
+                }
+            }
+        }
+        GameEvent::Won { winner } => {
+            state.winner = Some(winner.clone());
+            state.phase = GamePhase::Ended;
+        }
+    }
+}
+
+fn find_player(state: &mut GameState, user_id: u32) -> Option<&mut PlayerSnapshot> {
+    state.players.iter_mut().find(|p| p.user_id == user_id)
+}
@@ -1,24 +1,30 @@
 #![feature(proc_macro_hygiene, decl_macro)]
-#![feature(drain_filter)]
 
 #[macro_use]
 extern crate rocket;
 
 use crate::api::auth::SessionID;
+use crate::api::net_types::{GamePhase, SessionSettings};
+use crate::database::{MemoryStorage, SqliteStorage, Storage};
 use log::{error, info, Level};
 use rocket::response;
 use rocket::Config;
 use rocket_contrib::serve::StaticFiles;
-use serde::Serialize;
+use std::convert::TryFrom;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::SystemTime;
 
 mod api;
+mod backup;
 mod page_hosting;
 mod database;
+mod game_log;
+mod moderation;
 mod notify;
+mod retention;
 
 use page_hosting::*;
 
@@ -29,18 +35,103 @@ pub struct SessionData {
     created: SystemTime,
     active: bool,
     settings: Option<String>,
+    phase: GamePhase,
+    player_count: u32,
 }
 
-pub const DIST_BASE: &'static str = "../webapp/dist/";
+impl SessionData {
+    pub fn created_secs(&self) -> u64 {
+        self.created
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// falls back to the defaults if nothing or something unreadable is stored
+    pub fn settings(&self) -> SessionSettings {
+        self.settings
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s).ok())
+            .unwrap_or_default()
+    }
+}
+
+pub const DIST_BASE: &str = "../webapp/dist/";
+
+/// file of the sqlite storage
+const DB_PATH: &str = "test.sqlite";
 
 #[get("/")]
 fn start_get() -> Option<response::NamedFile> {
     response::NamedFile::open([DIST_BASE, "user_ui/login/login.html"].concat()).ok()
 }
 
+/// STORAGE=memory runs without a database file, DEV_SESSIONS (comma separated ids)
+/// creates sessions to join, as nothing else does
+fn open_storage() -> Result<Arc<dyn Storage>, String> {
+    if std::env::var("STORAGE").ok().as_deref() != Some("memory") {
+        let storage = SqliteStorage::open(DB_PATH.as_ref()).map_err(|e| e.to_string())?;
+        return Ok(Arc::new(storage));
+    }
+
+    info!("Using in-memory storage, nothing will be persisted");
+    let storage = MemoryStorage::new();
+    let dev_sessions = std::env::var("DEV_SESSIONS").unwrap_or_default();
+    for sid in dev_sessions.split(',').filter(|sid| !sid.is_empty()) {
+        let sid = SessionID::try_from(sid).map_err(|e| format!("{}: {}", sid, e))?;
+        storage.create_session(&sid).map_err(|e| e.to_string())?;
+        info!("Created dev session {}", sid);
+    }
+    Ok(Arc::new(storage))
+}
+
+/// `server backup` writes a backup and exits, works while another instance is running.
+/// `server backup list` lists the backups, `server restore <name>` restores one and starts.
+/// Returns true if the server should start afterwards
+fn run_command(args: &[String], config: &backup::BackupConfig) -> Result<bool, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => Ok(true),
+        ["backup"] => {
+            let created = backup::create_from_file(DB_PATH.as_ref(), config)
+                .map_err(|e| e.to_string())?;
+            println!("{}", config.dir.join(created.name).display());
+            Ok(false)
+        }
+        ["backup", "list"] => {
+            for backup in config.list().map_err(|e| e.to_string())? {
+                println!("{}\t{} bytes", backup.name, backup.size);
+            }
+            Ok(false)
+        }
+        ["restore", name] => {
+            backup::restore(DB_PATH.as_ref(), name, config).map_err(|e| e.to_string())?;
+            Ok(true)
+        }
+        _ => Err("Usage: server [backup | backup list | restore <name>]".into()),
+    }
+}
+
 fn main() -> std::io::Result<()> {
     simple_logger::init_with_level(Level::Info).unwrap();
 
+    let backup_config = match backup::BackupConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid backup config: {}", e);
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+    };
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run_command(&args, &backup_config) {
+        Ok(true) => {}
+        Ok(false) => return Ok(()),
+        Err(e) => {
+            error!("{}", e);
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+    }
+
     ctrlc::set_handler(move || {
         SHOULD_TERMINATE.store(true, Ordering::Relaxed);
     })
@@ -56,7 +147,7 @@ fn main() -> std::io::Result<()> {
 
     info!("Opening database...");
 
-    let db = match database::Database::open("test.sqlite".as_ref()) {
+    let db = match open_storage() {
         Ok(db) => db,
         Err(e) => {
             error!("Failed to open database-connection: {}", e);
@@ -65,18 +156,47 @@ fn main() -> std::io::Result<()> {
     };
 
     info!("Starting WebSocket Service...");
-    let mut ws_addr = addr.clone();
-    ws_addr.set_port(3031);
-    let notifier = notify::start(ws_addr)?;
+    // rocket can't hand upgraded connections over, so the websocket needs its own port.
+    // Deployments behind a reverse proxy set WS_BIND and WS_PUBLIC_URL to the proxied address
+    let ws_config = match notify::NotifierConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Invalid websocket config: {}", e);
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+    };
+    let notifier = notify::start(ws_config)?;
+
+    let moderation = match moderation::ModerationConfig::from_env() {
+        Ok(config) => moderation::Moderation::new(config),
+        Err(e) => {
+            error!("Invalid moderation config: {}", e);
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+    };
+
+    let retention = match retention::RetentionConfig::from_env() {
+        Ok(config) => Arc::new(retention::Retention::new(config)),
+        Err(e) => {
+            error!("Invalid retention config: {}", e);
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+    };
+    retention::start(Arc::clone(&retention), Arc::clone(&db), notifier.clone())?;
 
     let mut config = Config::development();
     config.set_port(3030);
     config.set_workers(4);
-    config.set_address("0.0.0.0");
+    config
+        .set_address("0.0.0.0")
+        .expect("0.0.0.0 is a valid address");
 
     let mut rocket = rocket::custom(config)
         .manage(db)
         .manage(notifier)
+        .manage(moderation)
+        .manage(retention)
+        .manage(backup_config)
         .mount("/", routes![start_get])
         .mount("/static", static_files);
 
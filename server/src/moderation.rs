@@ -0,0 +1,299 @@
+use crate::api::auth::SessionID;
+use crate::api::net_types::MuteInfo;
+use log::info;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// log target of all moderation actions, so they can be filtered out of the log
+pub const MOD_LOG_TARGET: &str = "Moderation";
+
+/// longest mute an admin can set
+pub const MAX_MUTE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// how often expired mutes and idle rate limits get dropped
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FilterMode {
+    /// replace the blocked words with *
+    Mask,
+    /// refuse the whole message
+    Reject,
+}
+
+pub struct ModerationConfig {
+    /// max posts in a burst
+    pub bucket_capacity: f64,
+    /// posts regained per second
+    pub refill_per_sec: f64,
+    /// lowercase words, matched case insensitive against whole words
+    pub blocked_words: Vec<String>,
+    pub filter_mode: FilterMode,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        ModerationConfig {
+            bucket_capacity: 5.0,
+            refill_per_sec: 0.5,
+            blocked_words: Vec::new(),
+            filter_mode: FilterMode::Mask,
+        }
+    }
+}
+
+impl ModerationConfig {
+    /// reads CHAT_FILTER_FILE (one word per line) and CHAT_FILTER_MODE (mask or reject)
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = ModerationConfig::default();
+
+        if let Ok(path) = std::env::var("CHAT_FILTER_FILE") {
+            let words = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read word filter {}: {}", path, e))?;
+            config.blocked_words = words
+                .lines()
+                .map(|w| w.trim().to_lowercase())
+                .filter(|w| !w.is_empty())
+                .collect();
+        }
+
+        if let Ok(mode) = std::env::var("CHAT_FILTER_MODE") {
+            config.filter_mode = match mode.as_str() {
+                "mask" => FilterMode::Mask,
+                "reject" => FilterMode::Reject,
+                other => return Err(format!("Unknown CHAT_FILTER_MODE {}", other)),
+            };
+        }
+
+        Ok(config)
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// tokens after refilling up to now
+    fn refilled(&self, now: Instant, config: &ModerationConfig) -> f64 {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        (self.tokens + elapsed * config.refill_per_sec).min(config.bucket_capacity)
+    }
+}
+
+struct Mute {
+    until: SystemTime,
+    reason: Option<String>,
+}
+
+/// why a message didn't pass moderation
+pub enum Rejection {
+    Muted {
+        remaining: Duration,
+        reason: Option<String>,
+    },
+    RateLimited,
+    BlockedWords,
+}
+
+/// chat moderation state shared by all request handlers
+pub struct Moderation {
+    config: ModerationConfig,
+    mutes: Mutex<HashMap<(SessionID, u32), Mute>>,
+    buckets: Mutex<HashMap<u32, TokenBucket>>,
+    last_prune: Mutex<Instant>,
+}
+
+impl Moderation {
+    pub fn new(config: ModerationConfig) -> Self {
+        info!(
+            target: MOD_LOG_TARGET,
+            "{} blocked words, mode {:?}",
+            config.blocked_words.len(),
+            config.filter_mode
+        );
+        Moderation {
+            config,
+            mutes: Mutex::new(HashMap::new()),
+            buckets: Mutex::new(HashMap::new()),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Err with the reason if the duration is longer than MAX_MUTE
+    pub fn mute(
+        &self,
+        sid: SessionID,
+        user_id: u32,
+        duration: Duration,
+        reason: Option<String>,
+    ) -> Result<(), String> {
+        if duration > MAX_MUTE {
+            return Err(format!(
+                "Mutes can last at most {} days",
+                MAX_MUTE.as_secs() / (24 * 60 * 60)
+            ));
+        }
+        let until = SystemTime::now()
+            .checked_add(duration)
+            .ok_or_else(|| "Mute duration out of range".to_owned())?;
+
+        info!(
+            target: MOD_LOG_TARGET,
+            "muted user {} in {} for {}s, reason: {:?}",
+            user_id,
+            sid,
+            duration.as_secs(),
+            reason
+        );
+        self.mutes
+            .lock()
+            .unwrap()
+            .insert((sid, user_id), Mute { until, reason });
+        Ok(())
+    }
+
+    /// false if the user wasn't muted
+    pub fn unmute(&self, sid: SessionID, user_id: u32) -> bool {
+        let removed = self.mutes.lock().unwrap().remove(&(sid, user_id)).is_some();
+        if removed {
+            info!(target: MOD_LOG_TARGET, "unmuted user {} in {}", user_id, sid);
+        }
+        removed
+    }
+
+    /// currently active mutes of the session, expired ones get removed
+    pub fn mutes(&self, sid: SessionID) -> Vec<MuteInfo> {
+        let now = SystemTime::now();
+        let mut mutes = self.mutes.lock().unwrap();
+        mutes.retain(|_, mute| mute.until > now);
+        mutes
+            .iter()
+            .filter(|((mute_sid, _), _)| *mute_sid == sid)
+            .map(|((_, user_id), mute)| MuteInfo {
+                user_id: *user_id,
+                until: mute
+                    .until
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                reason: mute.reason.clone(),
+            })
+            .collect()
+    }
+
+    /// checks mute and rate limit of a player and filters the message,
+    /// Ok with the message to store. Call it after every other check,
+    /// only a message that passes the filter uses up a post of the rate limit
+    pub fn check_post(
+        &self,
+        sid: SessionID,
+        user_id: u32,
+        message: &str,
+    ) -> Result<String, Rejection> {
+        self.prune();
+
+        if let Some(mute) = self.mutes.lock().unwrap().get(&(sid, user_id)) {
+            if let Ok(remaining) = mute.until.duration_since(SystemTime::now()) {
+                return Err(Rejection::Muted {
+                    remaining,
+                    reason: mute.reason.clone(),
+                });
+            }
+        }
+
+        let (filtered, blocked) = self.filter(message);
+        if blocked {
+            info!(
+                target: MOD_LOG_TARGET,
+                "filtered message of user {} in {} ({:?})",
+                user_id,
+                sid,
+                self.config.filter_mode
+            );
+            if self.config.filter_mode == FilterMode::Reject {
+                return Err(Rejection::BlockedWords);
+            }
+        }
+
+        if !self.take_token(user_id) {
+            info!(target: MOD_LOG_TARGET, "rate limited user {} in {}", user_id, sid);
+            return Err(Rejection::RateLimited);
+        }
+        Ok(filtered)
+    }
+
+    /// drops expired mutes and full buckets, a missing bucket starts full anyway
+    fn prune(&self) {
+        let now = Instant::now();
+        {
+            let mut last_prune = self.last_prune.lock().unwrap();
+            if now.duration_since(*last_prune) < PRUNE_INTERVAL {
+                return;
+            }
+            *last_prune = now;
+        }
+
+        let now_sys = SystemTime::now();
+        self.mutes
+            .lock()
+            .unwrap()
+            .retain(|_, mute| mute.until > now_sys);
+        self.buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| bucket.refilled(now, &self.config) < self.config.bucket_capacity);
+    }
+
+    fn take_token(&self, user_id: u32) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(user_id).or_insert(TokenBucket {
+            tokens: self.config.bucket_capacity,
+            last_refill: now,
+        });
+
+        bucket.tokens = bucket.refilled(now, &self.config);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// masks every blocked word, returns if any was found
+    fn filter(&self, message: &str) -> (String, bool) {
+        if self.config.blocked_words.is_empty() {
+            return (message.to_owned(), false);
+        }
+
+        let mut filtered = String::with_capacity(message.len());
+        let mut blocked = false;
+        let mut word = String::new();
+        // a trailing separator flushes the last word
+        for c in message.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if !word.is_empty() {
+                if self.config.blocked_words.contains(&word.to_lowercase()) {
+                    blocked = true;
+                    filtered.extend(word.chars().map(|_| '*'));
+                } else {
+                    filtered.push_str(&word);
+                }
+                word.clear();
+            }
+            filtered.push(c);
+        }
+        filtered.pop();
+
+        (filtered, blocked)
+    }
+}
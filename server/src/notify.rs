@@ -1,122 +1,249 @@
 use crate::api::auth::SessionID;
+use crate::api::net_types::{ChatMessage, Presence};
 use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream};
 use std::sync::atomic::{self, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tungstenite::handshake::server::ErrorResponse;
-use tungstenite::protocol::WebSocket;
+use tungstenite::http::{HeaderValue, StatusCode};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::{CloseFrame, WebSocket};
 use tungstenite::Message;
 
 pub enum Notification {
     UpdatePlayerList(SessionID),
+    /// sessions got created, closed or changed their player count
+    UpdateSessionList,
     CustomToPlayer(u64, String),
     CustomToSession(SessionID, String),
-    UpdateConnectionsAlive(Arc<atomic::AtomicI64>),
+    /// the message and the players allowed to read it, controllers always receive it
+    ChatMessage(SessionID, ChatMessage, HashSet<u32>),
+    /// the game of the session is over, its replay buffer can go
+    SessionClosed(SessionID),
+    /// the session got removed from the database, nothing of it is kept
+    SessionDeleted(SessionID),
 }
 
-pub struct Notifier(Mutex<mpsc::Sender<Notification>>);
+pub struct Notifier {
+    sender: Mutex<mpsc::Sender<Notification>>,
+    presence: Arc<PresenceTracker>,
+    /// open websockets, kept up to date by the worker
+    connections: Arc<atomic::AtomicU32>,
+    public_url: Option<String>,
+    port: u16,
+}
 
 impl Notifier {
+    pub fn presence(&self, user_id: u32) -> Presence {
+        self.presence.get(user_id)
+    }
+
+    /// players with at least one open websocket
+    pub fn players_online(&self) -> u32 {
+        self.presence.online()
+    }
+
+    /// open websockets of players and controllers, including unauthenticated ones
+    pub fn connections(&self) -> u32 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// url clients should connect to, if the websocket is reachable under another address
+    /// than host:port of the http server (e.g. behind a reverse proxy)
+    pub fn public_url(&self) -> Option<&str> {
+        self.public_url.as_deref()
+    }
+
+    /// port the websocket listener is bound to
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
     pub fn send(&self, msg: Notification) {
-        match self.0.lock().unwrap().send(msg) {
-            Err(_) => {
-                error!("Failed to send Notification");
-            }
-            _ => {}
+        if self.sender.lock().unwrap().send(msg).is_err() {
+            error!("Failed to send Notification");
+        }
+    }
+}
+
+/// another handle to the same worker, for threads outside of rocket
+impl Clone for Notifier {
+    fn clone(&self) -> Self {
+        Notifier {
+            sender: Mutex::new(self.sender.lock().unwrap().clone()),
+            presence: Arc::clone(&self.presence),
+            connections: Arc::clone(&self.connections),
+            public_url: self.public_url.clone(),
+            port: self.port,
         }
     }
 }
 
-pub fn start(addr: SocketAddr) -> std::io::Result<Notifier> {
+/// what happens if the outbound queue of a connection is full
+#[derive(Clone, Copy, Debug)]
+pub enum OverflowPolicy {
+    /// drop the oldest queued frame
+    DropOldest,
+    /// replace an older queued frame of the same refresh event (like update.playerlist),
+    /// fall back to dropping the oldest frame
+    Coalesce,
+    /// close the connection, the client reconnects and resumes
+    Disconnect,
+}
+
+pub struct NotifierConfig {
+    /// where the websocket listens, a port of its own next to the http server
+    pub bind: SocketAddr,
+    /// max frames queued per connection that couldn't be written yet
+    pub queue_len: usize,
+    pub overflow: OverflowPolicy,
+    /// advertised to clients instead of ws://<http host>:<port>/
+    pub public_url: Option<String>,
+}
+
+impl Default for NotifierConfig {
+    fn default() -> Self {
+        NotifierConfig {
+            bind: ([127, 0, 0, 1], 3031).into(),
+            queue_len: 32,
+            overflow: OverflowPolicy::Coalesce,
+            public_url: None,
+        }
+    }
+}
+
+/// more frames per connection only delay noticing a client that doesn't read
+const MAX_QUEUE_LEN: usize = 4096;
+
+impl NotifierConfig {
+    /// reads WS_BIND (ip:port, default 127.0.0.1:3031), WS_PUBLIC_URL,
+    /// WS_QUEUE_LEN (1 to 4096) and WS_OVERFLOW (drop_oldest, coalesce or disconnect)
+    pub fn from_env() -> Result<Self, String> {
+        NotifierConfig::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = NotifierConfig {
+            public_url: var("WS_PUBLIC_URL"),
+            ..Default::default()
+        };
+
+        if let Some(bind) = var("WS_BIND") {
+            config.bind = bind
+                .parse()
+                .map_err(|_| format!("WS_BIND must be like 127.0.0.1:3031, not {}", bind))?;
+        }
+
+        if let Some(len) = var("WS_QUEUE_LEN") {
+            config.queue_len = match len.parse() {
+                Ok(len) if (1..=MAX_QUEUE_LEN).contains(&len) => len,
+                _ => {
+                    return Err(format!(
+                        "WS_QUEUE_LEN must be a number from 1 to {}, not {}",
+                        MAX_QUEUE_LEN, len
+                    ))
+                }
+            };
+        }
+
+        if let Some(overflow) = var("WS_OVERFLOW") {
+            config.overflow = match overflow.as_str() {
+                "drop_oldest" => OverflowPolicy::DropOldest,
+                "coalesce" => OverflowPolicy::Coalesce,
+                "disconnect" => OverflowPolicy::Disconnect,
+                other => return Err(format!("Unknown WS_OVERFLOW {}", other)),
+            };
+        }
+
+        Ok(config)
+    }
+}
+
+pub fn start(config: NotifierConfig) -> std::io::Result<Notifier> {
     info!(
         target: WS_LOG_TARGET,
-        "Initializing WebSocketHandler on addr {:?}", addr
+        "Initializing WebSocketHandler on addr {:?}", config.bind
     );
 
+    // bound here, so a taken port fails the start instead of the worker
+    let server = TcpListener::bind(config.bind)?;
+    server.set_nonblocking(true)?;
+    let port = server.local_addr()?.port();
+
     let (sender, receiver) = mpsc::channel();
+    let presence = Arc::new(PresenceTracker::default());
+    let handler_presence = Arc::clone(&presence);
+    let connections = Arc::new(atomic::AtomicU32::new(0));
+    let handler_connections = Arc::clone(&connections);
+    let public_url = config.public_url.clone();
 
     std::thread::Builder::new()
         .name("WebsocketWorker".into())
         .spawn(move || {
-            // create server
-            let server = TcpListener::bind(addr).unwrap();
-            server.set_nonblocking(true);
-
-            let mut handler = WebsocketHandler::new(receiver);
+            let mut handler =
+                WebsocketHandler::new(receiver, handler_presence, handler_connections, config);
 
             while !crate::SHOULD_TERMINATE.load(Ordering::Relaxed) {
                 // check for new incoming data
                 while let Ok((new_stream, client_addr)) = server.accept() {
-                    // hacky to get session id from request
-
-                    // if the inner option contains Some(sid), it was from and player
-                    // else if the inner Option is None, it came from and controller
-                    let mut conn_data: Cell<Option<Option<SessionID>>> = Cell::new(None);
+                    // filled by the handshake callback, if the upgrade request was accepted
+                    let conn_auth: Cell<Option<ConnectionAuth>> = Cell::new(None);
 
-                    if let Ok(ws) = tungstenite::accept_hdr(
+                    if let Ok(mut ws) = tungstenite::accept_hdr(
                         new_stream,
                         |req: &tungstenite::handshake::server::Request,
-                         res: tungstenite::handshake::server::Response| {
+                         mut res: tungstenite::handshake::server::Response| {
                             // --- CALLBACK START ---
-                            // validate ws-upgrade was called with valid token
+                            let offered = req
+                                .headers()
+                                .get("Sec-WebSocket-Protocol")
+                                .and_then(|h| h.to_str().ok());
 
-                            let mut uri_path = req.uri().path();
-
-                            if uri_path.len() < 4 {
-                                return Err(ErrorResponse::new(Some("no valid token".to_owned())));
-                            }
-                            // remove / from uri
-                            uri_path = uri_path
-                                .chars()
-                                .next()
-                                .map(|c| &uri_path[c.len_utf8()..])
-                                .unwrap();
-                            info!("Parsing {}", uri_path);
-                            // parse
-                            use crate::api::auth::PlayerAuthToken;
-                            match PlayerAuthToken::try_from(uri_path) {
-                                Ok(at) => {
-                                    info!(target: WS_LOG_TARGET, "got valid request: {:?}", &at);
-                                    conn_data.set(Some(Some(at.session_id)));
-                                    return Ok(res);
-                                }
-                                Err(_) => {
-                                    error!(
-                                        target: WS_LOG_TARGET,
-                                        "could not parse user token, trying \
-                            with admin"
-                                    );
+                            match ConnectionAuth::from_protocols(offered, Instant::now()) {
+                                Some(auth) => {
+                                    // browsers drop the connection if none of the offered
+                                    // protocols gets selected
+                                    if !auth.is_pending() {
+                                        res.headers_mut().insert(
+                                            "Sec-WebSocket-Protocol",
+                                            HeaderValue::from_static(WS_AUTH_PROTOCOL),
+                                        );
+                                    }
+                                    conn_auth.set(Some(auth));
+                                    Ok(res)
                                 }
-                            }
-
-                            use crate::api::auth::AdminAuthToken;
-                            match AdminAuthToken::try_from(uri_path) {
-                                Ok(at) => {
-                                    info!("Got valid admin request: {:?}", &at);
-                                    conn_data.set(Some(None));
-                                    return Ok(res);
+                                None => {
+                                    let mut err =
+                                        ErrorResponse::new(Some("Invalid token".to_owned()));
+                                    *err.status_mut() = StatusCode::UNAUTHORIZED;
+                                    Err(err)
                                 }
-                                Err(_) => error!(target: WS_LOG_TARGET, "No valid admin token"),
                             }
 
-                            Err(ErrorResponse::new(Some("Invalid token".to_owned())))
-
                             // --- CALLBACK END ---
                         },
                     ) {
-                        if let Some(inner) = conn_data.replace(None) {
+                        if let Some(auth) = conn_auth.replace(None) {
                             info!(
                                 target: WS_LOG_TARGET,
                                 "New WS connection from {:?}", client_addr
                             );
-                            let conn = match inner {
-                                Some(sid) => WSConnection::Player(sid, ws),
-                                None => WSConnection::Controller(ws),
-                            };
-                            handler.add_socket(conn);
+                            // the handshake is done blocking, everything after that must not
+                            // stall the worker
+                            if let Err(e) = ws.get_mut().set_nonblocking(true) {
+                                error!(
+                                    target: WS_LOG_TARGET,
+                                    "Failed to set ws to nonblocking: {}", e
+                                );
+                                continue;
+                            }
+                            handler.add_socket(WSConnection::new(ws, auth));
                         }
                     }
                 }
@@ -125,6 +252,8 @@ pub fn start(addr: SocketAddr) -> std::io::Result<Notifier> {
                 // process notifications to send
                 handler.poll_messages();
                 handler.handle_notifications();
+                handler.flush();
+                handler.prune_logs(Instant::now());
 
                 if handler.needs_cleanup() {
                     handler.clean_up()
@@ -133,28 +262,607 @@ pub fn start(addr: SocketAddr) -> std::io::Result<Notifier> {
 
             // cleanup
             handler.terminate();
+        })?;
+
+    Ok(Notifier {
+        sender: Mutex::new(sender),
+        presence,
+        connections,
+        public_url,
+        port,
+    })
+}
+
+/// counts the open connections per player, so the api can tell who is online right now
+#[derive(Default)]
+pub struct PresenceTracker(Mutex<HashMap<u32, PresenceEntry>>);
+
+struct PresenceEntry {
+    session: SessionID,
+    connections: u32,
+    last_seen: u64,
+}
+
+impl PresenceTracker {
+    pub fn get(&self, user_id: u32) -> Presence {
+        match self.0.lock().unwrap().get(&user_id) {
+            Some(entry) if entry.connections > 0 => Presence::Online,
+            Some(entry) => Presence::Offline {
+                last_seen: Some(entry.last_seen),
+            },
+            None => Presence::Offline { last_seen: None },
+        }
+    }
+
+    /// returns true if the player was offline before
+    fn connect(&self, sid: SessionID, user_id: u32) -> bool {
+        let mut entries = self.0.lock().unwrap();
+        let entry = entries.entry(user_id).or_insert(PresenceEntry {
+            session: sid,
+            connections: 0,
+            last_seen: 0,
         });
+        entry.connections += 1;
+        entry.last_seen = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        entry.connections == 1
+    }
+
+    /// returns true if this was the last connection of the player
+    fn disconnect(&self, user_id: u32) -> bool {
+        let mut entries = self.0.lock().unwrap();
+        match entries.get_mut(&user_id) {
+            Some(entry) if entry.connections > 0 => {
+                entry.connections -= 1;
+                entry.last_seen = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+                entry.connections == 0
+            }
+            _ => false,
+        }
+    }
+
+    pub fn online(&self) -> u32 {
+        self.0
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.connections > 0)
+            .count() as u32
+    }
+
+    /// drops the entries of the sessions keep returns false for, their players read as
+    /// never seen afterwards
+    fn retain_sessions(&self, mut keep: impl FnMut(&SessionID) -> bool) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|_, entry| keep(&entry.session));
+    }
+}
+
+/// subprotocol a browser client offers together with its token:
+/// `new WebSocket(url, [WS_AUTH_PROTOCOL, token])`
+const WS_AUTH_PROTOCOL: &str = "werwolf.auth";
+
+/// how long a connection may stay open without sending an auth frame
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// application defined close code, sent when the token of a connection runs out.
+/// The client has to get a new token before reconnecting
+const CLOSE_TOKEN_EXPIRED: u16 = 4001;
 
-    Ok(Notifier(Mutex::new(sender)))
+/// frames a client may send over the websocket, as json
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientFrame {
+    /// authenticates a pending connection or refreshes the token of an authenticated one
+    Auth { token: String },
+    /// controller only: receive the notifications of the session
+    Subscribe { session_id: String },
+    /// controller only: stop receiving the notifications of the session
+    Unsubscribe { session_id: String },
+    /// controller only: (un-)subscribe the session list overview channel
+    Overview { enabled: bool },
+    /// replay the events of the session the client missed since last_seq. epoch is the one
+    /// of the frame with last_seq, clients of an older version don't send it
+    Resume {
+        session_id: String,
+        epoch: Option<u32>,
+        last_seq: u64,
+    },
 }
 
-enum WSConnection {
-    Player(SessionID, WebSocket<TcpStream>),
-    Controller(WebSocket<TcpStream>),
+/// frames the server sends over the websocket, as json
+#[derive(Serialize)]
+struct ServerFrame<'a> {
+    /// not set for events that aren't bound to a session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<&'a str>,
+    /// changes whenever seq starts over, e.g. after a restart of the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u32>,
+    /// increasing per session and epoch, not set for events that aren't bound to a session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    event: &'a str,
+    /// payload of the event, if it carries more than "something changed"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<&'a serde_json::Value>,
+}
+
+impl<'a> ServerFrame<'a> {
+    fn to_queued(&self, sid: Option<SessionID>) -> QueuedFrame {
+        QueuedFrame {
+            session_id: sid,
+            event: self.event.to_owned(),
+            text: serde_json::to_string(self).unwrap(),
+            recipients: None,
+        }
+    }
+}
+
+/// events that only tell the client to reload something, so a newer one replaces an older one
+const COALESCABLE_EVENTS: &[&str] = &[
+    "update.playerlist",
+    "update.presence",
+    "controller.sessionlist",
+];
+
+/// a serialized ServerFrame waiting to be written
+#[derive(Clone)]
+struct QueuedFrame {
+    session_id: Option<SessionID>,
+    event: String,
+    text: String,
+    /// players allowed to receive the frame, None if every connection of the session may
+    recipients: Option<Arc<HashSet<u32>>>,
+}
+
+impl QueuedFrame {
+    fn replaces(&self, older: &QueuedFrame) -> bool {
+        self.session_id == older.session_id
+            && self.event == older.event
+            && COALESCABLE_EVENTS.contains(&self.event.as_str())
+    }
+}
+
+fn would_block(e: &tungstenite::Error) -> bool {
+    match e {
+        tungstenite::Error::Io(e) => e.kind() == io::ErrorKind::WouldBlock,
+        _ => false,
+    }
+}
+
+/// what the handler has to do after a connection handled a frame
+enum FrameOutcome {
+    Done,
+    /// replay the events of the session after the epoch and sequence number
+    Resume(SessionID, Option<u32>, u64),
+}
+
+/// how many events per session are kept for reconnecting clients
+const REPLAY_BUFFER_LEN: usize = 64;
+
+/// event sent instead of the missed events if they aren't buffered anymore.
+/// The client has to reload everything it displays
+const RESYNC_EVENT: &str = "resync";
+
+/// logs without events for this long are dropped if no connection belongs to their session
+const SESSION_LOG_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// how often the idle logs are looked for
+const SESSION_LOG_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// the last events of a session, so clients can catch up after a reconnect
+struct SessionLog {
+    /// random, so a client can tell that its last_seq belongs to a log that is gone
+    epoch: u32,
+    next_seq: u64,
+    /// sequence number and frame, oldest first
+    replay: VecDeque<(u64, QueuedFrame)>,
+    last_event: Instant,
+}
+
+impl SessionLog {
+    fn new() -> Self {
+        SessionLog {
+            epoch: rand::random(),
+            next_seq: 1,
+            replay: VecDeque::with_capacity(REPLAY_BUFFER_LEN),
+            last_event: Instant::now(),
+        }
+    }
+
+    /// assigns the next sequence number to the event and returns the frame to send
+    fn push(
+        &mut self,
+        sid: &SessionID,
+        event: &str,
+        data: Option<&serde_json::Value>,
+        recipients: Option<HashSet<u32>>,
+    ) -> QueuedFrame {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.last_event = Instant::now();
+
+        let mut frame = ServerFrame {
+            session_id: Some(sid.as_str()),
+            epoch: Some(self.epoch),
+            seq: Some(seq),
+            event,
+            data,
+        }
+        .to_queued(Some(*sid));
+        frame.recipients = recipients.map(Arc::new);
+
+        if self.replay.len() == REPLAY_BUFFER_LEN {
+            self.replay.pop_front();
+        }
+        self.replay.push_back((seq, frame.clone()));
+        frame
+    }
+
+    /// frames after last_seq the connection may receive, or None if some of them aren't
+    /// buffered anymore. A client of another epoch saw a log that got pruned or a previous
+    /// run of the server
+    fn since(
+        &self,
+        epoch: Option<u32>,
+        last_seq: u64,
+        auth: &ConnectionAuth,
+    ) -> Option<Vec<&QueuedFrame>> {
+        if epoch != Some(self.epoch) || last_seq >= self.next_seq {
+            return None;
+        }
+        match self.replay.front() {
+            Some(&(oldest, _)) if oldest > last_seq + 1 => None,
+            _ => Some(
+                self.replay
+                    .iter()
+                    .filter(|(seq, frame)| *seq > last_seq && auth.may_receive(frame))
+                    .map(|(_, frame)| frame)
+                    .collect(),
+            ),
+        }
+    }
+
+    fn resync_frame(&self, sid: &SessionID) -> QueuedFrame {
+        ServerFrame {
+            session_id: Some(sid.as_str()),
+            epoch: Some(self.epoch),
+            seq: Some(self.next_seq - 1),
+            event: RESYNC_EVENT,
+            data: None,
+        }
+        .to_queued(Some(*sid))
+    }
+}
+
+enum ConnectionAuth {
+    /// upgraded, but no auth frame received yet
+    Pending(Instant),
+    /// session, user id and expiry of the token
+    Player(SessionID, u32, u64),
+    /// expiry of the token
+    Controller(u64),
+}
+
+impl ConnectionAuth {
+    fn from_token(token: &str) -> Option<ConnectionAuth> {
+        use crate::api::auth::{AdminAuthToken, PlayerAuthToken};
+
+        if let Ok(at) = PlayerAuthToken::try_from(token) {
+            info!(
+                target: WS_LOG_TARGET,
+                "Authenticated player {} of session {}", at.user_id, at.session_id
+            );
+            return Some(ConnectionAuth::Player(
+                at.session_id,
+                at.user_id,
+                at.basic.exp(),
+            ));
+        }
+
+        if let Ok(at) = AdminAuthToken::try_from(token) {
+            info!(target: WS_LOG_TARGET, "Authenticated controller");
+            return Some(ConnectionAuth::Controller(at.exp()));
+        }
+
+        warn!(target: WS_LOG_TARGET, "No valid player or admin token");
+        None
+    }
+
+    /// auth of a connection offering the Sec-WebSocket-Protocol header value. The token is
+    /// either offered as second subprotocol next to WS_AUTH_PROTOCOL or gets sent with the
+    /// first frame after the upgrade, it must never be part of the uri, as those end up in
+    /// access logs. None if the offered token is invalid
+    fn from_protocols(offered: Option<&str>, now: Instant) -> Option<ConnectionAuth> {
+        let offered: Vec<&str> = offered
+            .map(|h| h.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+
+        if offered.first() != Some(&WS_AUTH_PROTOCOL) {
+            return Some(ConnectionAuth::Pending(now));
+        }
+        offered.get(1).and_then(|t| ConnectionAuth::from_token(t))
+    }
+
+    fn may_receive(&self, frame: &QueuedFrame) -> bool {
+        match (&frame.recipients, self) {
+            (None, _) => true,
+            (Some(_), ConnectionAuth::Controller(_)) => true,
+            (Some(recipients), ConnectionAuth::Player(_, user_id, _)) => {
+                recipients.contains(user_id)
+            }
+            _ => false,
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        matches!(self, ConnectionAuth::Pending(_))
+    }
+
+    /// handles the token of an auth frame, returns the close frame to send if it is invalid
+    fn authenticate(&mut self, token: &str) -> Result<(), CloseFrame<'static>> {
+        match ConnectionAuth::from_token(token) {
+            Some(auth) => {
+                *self = auth;
+                Ok(())
+            }
+            None => Err(CloseFrame {
+                code: CloseCode::Policy,
+                reason: "invalid token".into(),
+            }),
+        }
+    }
+
+    /// returns the close frame to send if the connection isn't authenticated anymore
+    fn check(&self, now: Instant, now_secs: u64) -> Option<CloseFrame<'static>> {
+        match *self {
+            ConnectionAuth::Pending(since) if now.duration_since(since) > AUTH_TIMEOUT => {
+                Some(CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "authentication timeout".into(),
+                })
+            }
+            ConnectionAuth::Player(_, _, exp) | ConnectionAuth::Controller(exp)
+                if exp <= now_secs =>
+            {
+                Some(CloseFrame {
+                    code: CloseCode::from(CLOSE_TOKEN_EXPIRED),
+                    reason: "token expired".into(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// frames of a connection waiting for its socket to become writable
+#[derive(Default)]
+struct OutboundQueue {
+    frames: VecDeque<QueuedFrame>,
+    /// sessions of frames dropped because of an overflow, the client needs a resync
+    lost: HashSet<SessionID>,
+}
+
+impl OutboundQueue {
+    fn is_full(&self, config: &NotifierConfig) -> bool {
+        self.frames.len() >= config.queue_len
+    }
+
+    fn has_resync_queued(&self, sid: &SessionID) -> bool {
+        self.frames
+            .iter()
+            .any(|f| f.session_id.as_ref() == Some(sid) && f.event == RESYNC_EVENT)
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(sid) = self.frames.pop_front().and_then(|f| f.session_id) {
+            self.lost.insert(sid);
+        }
+    }
+
+    /// sessions that lost frames and have no resync queued yet
+    fn take_lost(&mut self) -> Vec<SessionID> {
+        let lost: Vec<SessionID> = self.lost.drain().collect();
+        lost.into_iter()
+            .filter(|sid| !self.has_resync_queued(sid))
+            .collect()
+    }
+
+    /// queues the frame, returns false if the queue is full and the connection has to be
+    /// closed
+    fn push(&mut self, frame: QueuedFrame, config: &NotifierConfig) -> bool {
+        if frame.event == RESYNC_EVENT {
+            // the client reloads everything of the session, the frames before are covered
+            self.frames
+                .retain(|older| older.session_id != frame.session_id);
+        }
+
+        if self.is_full(config) {
+            match config.overflow {
+                OverflowPolicy::Disconnect => return false,
+                OverflowPolicy::Coalesce => {
+                    match self.frames.iter().position(|older| frame.replaces(older)) {
+                        Some(idx) => {
+                            self.frames.remove(idx);
+                        }
+                        None => self.drop_oldest(),
+                    }
+                }
+                OverflowPolicy::DropOldest => self.drop_oldest(),
+            }
+        }
+        self.frames.push_back(frame);
+        true
+    }
+}
+
+struct WSConnection {
+    ws: WebSocket<TcpStream>,
+    auth: ConnectionAuth,
+    /// sessions a controller subscribed to, always empty for players
+    subscriptions: HashSet<SessionID>,
+    /// if a controller subscribed to the session list overview channel
+    overview: bool,
+    outbound: OutboundQueue,
 }
 
 impl WSConnection {
-    fn get_ws(&mut self) -> &mut WebSocket<TcpStream> {
-        match self {
-            WSConnection::Player(_, ws) => ws,
-            WSConnection::Controller(ws) => ws,
+    fn new(ws: WebSocket<TcpStream>, auth: ConnectionAuth) -> Self {
+        WSConnection {
+            ws,
+            auth,
+            subscriptions: HashSet::new(),
+            overview: false,
+            outbound: OutboundQueue::default(),
+        }
+    }
+
+    /// queues the frame for the next flush, returns false if the connection has to be closed
+    /// because its queue overflowed
+    fn queue(&mut self, frame: QueuedFrame, config: &NotifierConfig) -> bool {
+        if self.outbound.is_full(config) {
+            warn!(
+                target: WS_LOG_TARGET,
+                "Outbound queue of {:?} overflowed ({:?})",
+                self.ws.get_ref().peer_addr(),
+                config.overflow
+            );
+        }
+        if !self.outbound.push(frame, config) {
+            self.close(CloseFrame {
+                code: CloseCode::Again,
+                reason: "outbound queue overflow".into(),
+            });
+            return false;
         }
+        true
+    }
+
+    /// writes queued frames until the socket would block
+    fn flush(&mut self) -> tungstenite::Result<()> {
+        loop {
+            // only hand the next frame to tungstenite once everything before it got written,
+            // so waiting frames stay in the bounded outbound queue
+            match self.ws.write_pending() {
+                Err(ref e) if would_block(e) => return Ok(()),
+                res => res?,
+            }
+
+            let frame = match self.outbound.frames.pop_front() {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+
+            match self.ws.write_message(Message::Text(frame.text)) {
+                // the frame is buffered by tungstenite and goes out with the next write_pending
+                Err(ref e) if would_block(e) => return Ok(()),
+                res => res?,
+            }
+        }
+    }
+
+    fn get_ws(&mut self) -> &mut WebSocket<TcpStream> {
+        &mut self.ws
+    }
+
+    fn is_controller(&self) -> bool {
+        matches!(self.auth, ConnectionAuth::Controller(_))
     }
 
     fn associated_w_sid(&self, sid: &SessionID) -> bool {
-        match self {
-            WSConnection::Controller(_) => true,
-            WSConnection::Player(ref psid, _) => psid.eq(sid),
+        match self.auth {
+            ConnectionAuth::Pending(_) => false,
+            ConnectionAuth::Controller(_) => self.subscriptions.contains(sid),
+            ConnectionAuth::Player(ref psid, _, _) => psid.eq(sid),
+        }
+    }
+
+    /// session and user id, if authenticated as player
+    fn player(&self) -> Option<(SessionID, u32)> {
+        match self.auth {
+            ConnectionAuth::Player(sid, user_id, _) => Some((sid, user_id)),
+            _ => None,
+        }
+    }
+
+    fn wants_overview(&self) -> bool {
+        self.is_controller() && self.overview
+    }
+
+    /// handles a text frame sent by the client, returns the close frame to send if the
+    /// connection has to be closed
+    fn handle_frame(&mut self, text: &str) -> Result<FrameOutcome, CloseFrame<'static>> {
+        let frame: ClientFrame = match serde_json::from_str(text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!(target: WS_LOG_TARGET, "Ignoring malformed frame: {}", e);
+                return Ok(FrameOutcome::Done);
+            }
+        };
+
+        match frame {
+            ClientFrame::Auth { token } => {
+                self.auth.authenticate(&token)?;
+                Ok(FrameOutcome::Done)
+            }
+            ClientFrame::Resume {
+                session_id,
+                epoch,
+                last_seq,
+            } => match SessionID::try_from(session_id.as_str()) {
+                Ok(sid) if self.associated_w_sid(&sid) => {
+                    Ok(FrameOutcome::Resume(sid, epoch, last_seq))
+                }
+                _ => {
+                    warn!(
+                        target: WS_LOG_TARGET,
+                        "Can't resume session the connection isn't associated with"
+                    );
+                    Ok(FrameOutcome::Done)
+                }
+            },
+            _ if !self.is_controller() => {
+                warn!(
+                    target: WS_LOG_TARGET,
+                    "Ignoring subscription frame of non-controller"
+                );
+                Ok(FrameOutcome::Done)
+            }
+            ClientFrame::Subscribe { session_id } => {
+                match SessionID::try_from(session_id.as_str()) {
+                    Ok(sid) => {
+                        info!(target: WS_LOG_TARGET, "Controller subscribed to {}", sid);
+                        self.subscriptions.insert(sid);
+                    }
+                    Err(e) => warn!(target: WS_LOG_TARGET, "Can't subscribe: {}", e),
+                }
+                Ok(FrameOutcome::Done)
+            }
+            ClientFrame::Unsubscribe { session_id } => {
+                if let Ok(sid) = SessionID::try_from(session_id.as_str()) {
+                    info!(target: WS_LOG_TARGET, "Controller unsubscribed from {}", sid);
+                    self.subscriptions.remove(&sid);
+                }
+                Ok(FrameOutcome::Done)
+            }
+            ClientFrame::Overview { enabled } => {
+                self.overview = enabled;
+                Ok(FrameOutcome::Done)
+            }
+        }
+    }
+
+    fn close(&mut self, frame: CloseFrame<'static>) {
+        info!(
+            target: WS_LOG_TARGET,
+            "Closing ws {:?}: {}",
+            self.ws.get_ref().peer_addr(),
+            frame
+        );
+        if let Err(e) = self.ws.close(Some(frame)) {
+            warn!(target: WS_LOG_TARGET, "Failed to send close frame: {}", e);
         }
     }
 }
@@ -163,41 +871,239 @@ struct WebsocketHandler {
     message_queue: mpsc::Receiver<Notification>,
     connections: Vec<WSConnection>,
     dead_sockets: Vec<usize>,
+    presence: Arc<PresenceTracker>,
+    /// published for the stats, updated whenever connections changes
+    open_connections: Arc<atomic::AtomicU32>,
+    /// sessions where a player came online or went offline since the last notification round
+    presence_changed: Vec<SessionID>,
+    session_logs: HashMap<SessionID, SessionLog>,
+    last_log_prune: Instant,
+    config: NotifierConfig,
 }
 
 impl WebsocketHandler {
-    pub fn new(msg_queue: mpsc::Receiver<Notification>) -> Self {
+    pub fn new(
+        msg_queue: mpsc::Receiver<Notification>,
+        presence: Arc<PresenceTracker>,
+        open_connections: Arc<atomic::AtomicU32>,
+        config: NotifierConfig,
+    ) -> Self {
         WebsocketHandler {
             message_queue: msg_queue,
             connections: Vec::new(),
             dead_sockets: Vec::new(),
+            presence,
+            open_connections,
+            presence_changed: Vec::new(),
+            session_logs: HashMap::new(),
+            last_log_prune: Instant::now(),
+            config,
+        }
+    }
+
+    /// sends the event to every connection associated with the session
+    fn broadcast(
+        &mut self,
+        sid: SessionID,
+        event: &str,
+        data: Option<&serde_json::Value>,
+        recipients: Option<HashSet<u32>>,
+    ) {
+        let frame = self
+            .session_logs
+            .entry(sid)
+            .or_insert_with(SessionLog::new)
+            .push(&sid, event, data, recipients);
+
+        for (idx, client) in self.connections.iter_mut().enumerate() {
+            if client.associated_w_sid(&sid)
+                && client.auth.may_receive(&frame)
+                && !client.queue(frame.clone(), &self.config)
+            {
+                self.dead_sockets.push(idx);
+            }
+        }
+    }
+
+    /// sends the events of the session after last_seq to the connection, or a resync event
+    fn replay(&mut self, idx: usize, sid: SessionID, epoch: Option<u32>, last_seq: u64) {
+        let log = self
+            .session_logs
+            .entry(sid)
+            .or_insert_with(SessionLog::new);
+        let conn = &mut self.connections[idx];
+        let config = &self.config;
+
+        let alive = match log.since(epoch, last_seq, &conn.auth) {
+            Some(frames) => {
+                let frames: Vec<QueuedFrame> = frames.into_iter().cloned().collect();
+                info!(
+                    target: WS_LOG_TARGET,
+                    "Replaying {} events of {}",
+                    frames.len(),
+                    sid
+                );
+                frames.into_iter().all(|frame| conn.queue(frame, config))
+            }
+            None => {
+                info!(target: WS_LOG_TARGET, "Gap too large, resync of {}", sid);
+                conn.queue(log.resync_frame(&sid), config)
+            }
+        };
+
+        if !alive {
+            self.dead_sockets.push(idx);
+        }
+    }
+
+    /// writes the outbound queues, sockets that fail to write are marked dead
+    pub fn flush(&mut self) {
+        for (idx, conn) in self.connections.iter_mut().enumerate() {
+            // frames got dropped, so the client has to reload everything
+            for sid in conn.outbound.take_lost() {
+                let resync = self
+                    .session_logs
+                    .entry(sid)
+                    .or_insert_with(SessionLog::new)
+                    .resync_frame(&sid);
+                if !conn.queue(resync, &self.config) {
+                    self.dead_sockets.push(idx);
+                }
+            }
+
+            if let Err(e) = conn.flush() {
+                warn!(
+                    target: WS_LOG_TARGET,
+                    "Failed to write to ws {:?}: {}",
+                    conn.ws.get_ref().peer_addr(),
+                    e
+                );
+                self.dead_sockets.push(idx);
+            }
+        }
+    }
+
+    fn player_connected(&mut self, (sid, user_id): (SessionID, u32)) {
+        if self.presence.connect(sid, user_id) && !self.presence_changed.contains(&sid) {
+            self.presence_changed.push(sid);
+        }
+    }
+
+    fn player_disconnected(&mut self, (sid, user_id): (SessionID, u32)) {
+        if self.presence.disconnect(user_id) && !self.presence_changed.contains(&sid) {
+            self.presence_changed.push(sid);
         }
     }
 
     pub fn poll_messages(&mut self) {
-        for (idx, ws) in self.connections.iter_mut().map(|e| e.get_ws()).enumerate() {
+        let now = Instant::now();
+        let now_secs = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        // player identity before and after an auth frame changed it
+        let mut auth_changes = Vec::new();
+        let mut resumes = Vec::new();
+
+        for (idx, conn) in self.connections.iter_mut().enumerate() {
+            if let Some(frame) = conn.auth.check(now, now_secs) {
+                conn.close(frame);
+                self.dead_sockets.push(idx);
+                continue;
+            }
+
+            let ws = conn.get_ws();
             if !(ws.can_read() && ws.can_write()) {
                 self.dead_sockets.push(idx);
                 info!("Ws cant read or write: {:?}", ws.get_ref().peer_addr());
                 continue;
             }
 
-            match ws.read_message() {
-                Ok(Message::Close(_)) => {
-                    info!("Close message from: {:?}", ws.get_ref().peer_addr());
-                    self.dead_sockets.push(idx);
+            loop {
+                match conn.get_ws().read_message() {
+                    Ok(Message::Text(text)) => {
+                        let before = conn.player();
+                        let handled = conn.handle_frame(&text);
+                        if before != conn.player() {
+                            auth_changes.push((before, conn.player()));
+                        }
+
+                        match handled {
+                            Ok(FrameOutcome::Done) => {}
+                            Ok(FrameOutcome::Resume(sid, epoch, last_seq)) => {
+                                resumes.push((idx, sid, epoch, last_seq))
+                            }
+                            Err(frame) => {
+                                conn.close(frame);
+                                self.dead_sockets.push(idx);
+                                break;
+                            }
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        info!("Close message from: {:?}", conn.get_ws().get_ref().peer_addr());
+                        self.dead_sockets.push(idx);
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                        break;
+                    }
+                    Err(e) => {
+                        warn!(target: WS_LOG_TARGET, "Failed to read from ws: {}", e);
+                        self.dead_sockets.push(idx);
+                        break;
+                    }
                 }
-                _ => {}
             }
         }
+
+        for (before, after) in auth_changes {
+            if let Some(player) = before {
+                self.player_disconnected(player);
+            }
+            if let Some(player) = after {
+                self.player_connected(player);
+            }
+        }
+
+        for (idx, sid, epoch, last_seq) in resumes {
+            self.replay(idx, sid, epoch, last_seq);
+        }
+    }
+
+    /// drops the logs of sessions nobody is connected to and nothing happened in for a while,
+    /// together with the presence of sessions that have neither a log nor a connection
+    pub fn prune_logs(&mut self, now: Instant) {
+        if now.duration_since(self.last_log_prune) < SESSION_LOG_PRUNE_INTERVAL {
+            return;
+        }
+        self.last_log_prune = now;
+
+        let connections = &self.connections;
+        let before = self.session_logs.len();
+        self.session_logs.retain(|sid, log| {
+            now.duration_since(log.last_event) < SESSION_LOG_IDLE
+                || connections.iter().any(|conn| conn.associated_w_sid(sid))
+        });
+        if self.session_logs.len() < before {
+            info!(
+                target: WS_LOG_TARGET,
+                "Pruned {} idle session logs",
+                before - self.session_logs.len()
+            );
+        }
+
+        let session_logs = &self.session_logs;
+        self.presence.retain_sessions(|sid| {
+            session_logs.contains_key(sid)
+                || connections.iter().any(|conn| conn.associated_w_sid(sid))
+        });
     }
 
     pub fn needs_cleanup(&self) -> bool {
-        self.dead_sockets.len() > 0
+        !self.dead_sockets.is_empty()
     }
 
     pub fn clean_up(&mut self) {
-        if self.dead_sockets.len() == 0 {
+        if self.dead_sockets.is_empty() {
             info!("No sockets to remove");
             return;
         }
@@ -208,37 +1114,57 @@ impl WebsocketHandler {
         );
 
         self.dead_sockets.sort_unstable_by(|a, b| b.cmp(a));
+        self.dead_sockets.dedup();
         info!("{:?}", self.dead_sockets);
-        for &d_s_idx in &self.dead_sockets {
-            self.connections.remove(d_s_idx);
+        for d_s_idx in std::mem::take(&mut self.dead_sockets) {
+            if let Some(player) = self.connections.remove(d_s_idx).player() {
+                self.player_disconnected(player);
+            }
         }
-
-        self.dead_sockets.clear();
+        self.publish_connections();
 
         info!("Sucessfully cleaned up dead sockets :D");
     }
 
     pub fn handle_notifications(&mut self) {
+        for sid in std::mem::take(&mut self.presence_changed) {
+            self.broadcast(sid, "update.presence", None, None);
+        }
+
         while let Ok(msg) = self.message_queue.try_recv() {
             match msg {
                 Notification::UpdatePlayerList(sid) => {
-                    for client in self
-                        .connections
-                        .iter_mut()
-                        .filter(|e| e.associated_w_sid(&sid))
-                    {
-                        client
-                            .get_ws()
-                            .write_message(Message::Text("update.playerlist".to_owned()));
-                    }
+                    self.broadcast(sid, "update.playerlist", None, None);
                 }
-                Notification::UpdateConnectionsAlive(res) => {
-                    res.store(self.connections.len() as i64, Ordering::Relaxed);
-                    info!(
-                        target: WS_LOG_TARGET,
-                        "Updated shared alive-counter to {}",
-                        self.connections.len()
-                    );
+                Notification::CustomToSession(sid, event) => {
+                    self.broadcast(sid, &event, None, None);
+                }
+                Notification::ChatMessage(sid, msg, readers) => {
+                    let data = serde_json::to_value(msg).unwrap();
+                    self.broadcast(sid, "chat.message", Some(&data), Some(readers));
+                }
+                Notification::SessionClosed(sid) => {
+                    // clients resuming later get a resync, as the next log has another epoch
+                    self.session_logs.remove(&sid);
+                }
+                Notification::SessionDeleted(sid) => {
+                    self.session_logs.remove(&sid);
+                    self.presence.retain_sessions(|psid| *psid != sid);
+                }
+                Notification::UpdateSessionList => {
+                    let frame = ServerFrame {
+                        session_id: None,
+                        epoch: None,
+                        seq: None,
+                        event: "controller.sessionlist",
+                        data: None,
+                    }
+                    .to_queued(None);
+                    for (idx, client) in self.connections.iter_mut().enumerate() {
+                        if client.wants_overview() && !client.queue(frame.clone(), &self.config) {
+                            self.dead_sockets.push(idx);
+                        }
+                    }
                 }
                 _ => {
                     warn!(
@@ -252,19 +1178,509 @@ impl WebsocketHandler {
     }
 
     pub fn add_socket(&mut self, conn: WSConnection) {
-        self.connections.push(conn)
+        if let Some(player) = conn.player() {
+            self.player_connected(player);
+        }
+        self.connections.push(conn);
+        self.publish_connections();
+    }
+
+    fn publish_connections(&self) {
+        self.open_connections
+            .store(self.connections.len() as u32, Ordering::Relaxed);
     }
 
-    pub fn terminate(mut self) {
+    pub fn terminate(self) {
+        self.open_connections.store(0, Ordering::Relaxed);
         info!(
             target: WS_LOG_TARGET,
             "Terminating: closing all ws-connections..."
         );
         for mut conn in self.connections {
-            conn.get_ws().close(None);
+            conn.close(CloseFrame {
+                code: CloseCode::Away,
+                reason: "server shutting down".into(),
+            });
         }
         info!(target: WS_LOG_TARGET, "Terminated!");
     }
 }
 
-static WS_LOG_TARGET: &'static str = "WebSocket";
+static WS_LOG_TARGET: &str = "WebSocket";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth::player_token::PlayerState;
+    use crate::api::auth::{AdminAuthToken, PlayerAuthToken};
+
+    fn sid() -> SessionID {
+        SessionID::try_from("AB12CD34").unwrap()
+    }
+
+    fn player_token(user_id: u32) -> String {
+        PlayerAuthToken::get_jwt(
+            user_id,
+            sid(),
+            "Alice".to_owned(),
+            String::new(),
+            PlayerState::Alive,
+        )
+    }
+
+    fn close_code(frame: Option<CloseFrame>) -> Option<u16> {
+        frame.map(|frame| frame.code.into())
+    }
+
+    #[test]
+    fn subprotocol_token_authenticates_on_upgrade() {
+        let offered = format!("{}, {}", WS_AUTH_PROTOCOL, player_token(3));
+        match ConnectionAuth::from_protocols(Some(&offered), Instant::now()) {
+            Some(ConnectionAuth::Player(psid, 3, _)) => assert_eq!(psid, sid()),
+            _ => panic!("not authenticated as player 3"),
+        }
+
+        let offered = format!("{},{}", WS_AUTH_PROTOCOL, AdminAuthToken::get_jwt());
+        let auth = ConnectionAuth::from_protocols(Some(&offered), Instant::now());
+        assert!(matches!(auth, Some(ConnectionAuth::Controller(_))));
+    }
+
+    #[test]
+    fn invalid_subprotocol_token_is_refused() {
+        let offered = format!("{}, garbage", WS_AUTH_PROTOCOL);
+        assert!(ConnectionAuth::from_protocols(Some(&offered), Instant::now()).is_none());
+        assert!(ConnectionAuth::from_protocols(Some(WS_AUTH_PROTOCOL), Instant::now()).is_none());
+    }
+
+    #[test]
+    fn auth_frame_authenticates_pending_connection() {
+        for offered in &[None, Some("chat"), Some("chat, werwolf.auth")] {
+            let auth = ConnectionAuth::from_protocols(*offered, Instant::now()).unwrap();
+            assert!(auth.is_pending(), "{:?}", offered);
+        }
+
+        let mut auth = ConnectionAuth::Pending(Instant::now());
+        let refused = auth.authenticate("garbage").err();
+        assert_eq!(close_code(refused), Some(CloseCode::Policy.into()));
+        assert!(auth.is_pending());
+
+        auth.authenticate(&player_token(5)).unwrap();
+        assert!(matches!(auth, ConnectionAuth::Player(_, 5, _)));
+
+        // a new token replaces the identity of an authenticated connection
+        auth.authenticate(&AdminAuthToken::get_jwt()).unwrap();
+        assert!(matches!(auth, ConnectionAuth::Controller(_)));
+    }
+
+    #[test]
+    fn pending_connection_times_out() {
+        let since = Instant::now();
+        let auth = ConnectionAuth::Pending(since);
+
+        assert!(auth.check(since + AUTH_TIMEOUT, 0).is_none());
+        let timeout = auth.check(since + AUTH_TIMEOUT + Duration::from_millis(1), 0);
+        assert_eq!(close_code(timeout), Some(CloseCode::Policy.into()));
+    }
+
+    #[test]
+    fn expired_token_closes_with_4001() {
+        let now = Instant::now();
+        for auth in &[
+            ConnectionAuth::Player(sid(), 1, 100),
+            ConnectionAuth::Controller(100),
+        ] {
+            assert!(auth.check(now, 99).is_none());
+            assert_eq!(close_code(auth.check(now, 100)), Some(CLOSE_TOKEN_EXPIRED));
+        }
+    }
+
+    fn now_secs() -> u64 {
+        std::time::UNIX_EPOCH.elapsed().unwrap().as_secs()
+    }
+
+    fn last_seen(presence: Presence) -> Option<u64> {
+        match presence {
+            Presence::Online => panic!("player is online"),
+            Presence::Offline { last_seen } => last_seen,
+        }
+    }
+
+    #[test]
+    fn presence_counts_tabs_of_a_player() {
+        let tracker = PresenceTracker::default();
+        assert_eq!(last_seen(tracker.get(1)), None);
+
+        let before = now_secs();
+        assert!(tracker.connect(sid(), 1));
+        assert!(!tracker.connect(sid(), 1), "second tab isn't a change");
+        assert!(matches!(tracker.get(1), Presence::Online));
+        assert_eq!(tracker.online(), 1);
+
+        assert!(!tracker.disconnect(1), "one tab is still open");
+        assert!(matches!(tracker.get(1), Presence::Online));
+
+        assert!(tracker.disconnect(1));
+        assert!(!tracker.disconnect(1), "already offline");
+        assert_eq!(tracker.online(), 0);
+        let seen = last_seen(tracker.get(1)).unwrap();
+        assert!(seen >= before && seen <= now_secs());
+    }
+
+    fn handler() -> (mpsc::Sender<Notification>, WebsocketHandler) {
+        handler_with(NotifierConfig::default())
+    }
+
+    fn handler_with(config: NotifierConfig) -> (mpsc::Sender<Notification>, WebsocketHandler) {
+        let (sender, receiver) = mpsc::channel();
+        let handler = WebsocketHandler::new(
+            receiver,
+            Arc::new(PresenceTracker::default()),
+            Arc::new(atomic::AtomicU32::new(0)),
+            config,
+        );
+        (sender, handler)
+    }
+
+    #[test]
+    fn presence_of_pruned_session_is_dropped() {
+        let (_sender, mut handler) = handler();
+        let presence = Arc::clone(&handler.presence);
+        let other = SessionID::try_from("ZZ99YY88").unwrap();
+        presence.connect(sid(), 1);
+        presence.disconnect(1);
+        presence.connect(other, 2);
+        presence.disconnect(2);
+        handler.broadcast(other, "update.presence", None, None);
+
+        let later = Instant::now() + SESSION_LOG_PRUNE_INTERVAL;
+        handler.prune_logs(later);
+        assert_eq!(last_seen(presence.get(1)), None, "session without a log");
+        assert!(last_seen(presence.get(2)).is_some());
+
+        handler.prune_logs(later + SESSION_LOG_IDLE);
+        assert_eq!(last_seen(presence.get(2)), None, "log went idle");
+        assert!(presence.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn presence_of_deleted_session_is_dropped() {
+        let (sender, mut handler) = handler();
+        let presence = Arc::clone(&handler.presence);
+        let other = SessionID::try_from("ZZ99YY88").unwrap();
+        presence.connect(sid(), 1);
+        presence.disconnect(1);
+        presence.connect(other, 2);
+
+        sender.send(Notification::SessionDeleted(sid())).unwrap();
+        handler.handle_notifications();
+
+        assert_eq!(last_seen(presence.get(1)), None);
+        assert!(matches!(presence.get(2), Presence::Online));
+        assert_eq!(presence.0.lock().unwrap().len(), 1);
+    }
+
+    fn seqs(frames: Option<Vec<&QueuedFrame>>) -> Option<Vec<u64>> {
+        frames.map(|frames| {
+            frames
+                .iter()
+                .map(|frame| {
+                    let text: serde_json::Value = serde_json::from_str(&frame.text).unwrap();
+                    text["seq"].as_u64().unwrap()
+                })
+                .collect()
+        })
+    }
+
+    fn log_of(events: usize) -> SessionLog {
+        let mut log = SessionLog::new();
+        for _ in 0..events {
+            log.push(&sid(), "update.playerlist", None, None);
+        }
+        log
+    }
+
+    #[test]
+    fn session_log_replays_after_last_seq() {
+        let log = log_of(5);
+        let player = ConnectionAuth::Player(sid(), 1, u64::MAX);
+
+        assert_eq!(
+            seqs(log.since(Some(log.epoch), 2, &player)),
+            Some(vec![3, 4, 5])
+        );
+        assert_eq!(seqs(log.since(Some(log.epoch), 5, &player)), Some(vec![]));
+        assert_eq!(
+            seqs(log.since(Some(log.epoch), 0, &player)),
+            Some(vec![1, 2, 3, 4, 5])
+        );
+        // a seq the log never handed out belongs to another log
+        assert_eq!(seqs(log.since(Some(log.epoch), 6, &player)), None);
+    }
+
+    #[test]
+    fn session_log_resyncs_stale_epoch() {
+        let log = log_of(5);
+        let player = ConnectionAuth::Player(sid(), 1, u64::MAX);
+
+        assert_eq!(seqs(log.since(Some(log.epoch ^ 1), 2, &player)), None);
+        assert_eq!(seqs(log.since(None, 2, &player)), None);
+
+        let resync: serde_json::Value =
+            serde_json::from_str(&log.resync_frame(&sid()).text).unwrap();
+        assert_eq!(resync["event"], RESYNC_EVENT);
+        assert_eq!(resync["epoch"], log.epoch);
+        assert_eq!(resync["seq"], 5);
+    }
+
+    #[test]
+    fn session_log_resyncs_past_replay_buffer() {
+        let log = log_of(REPLAY_BUFFER_LEN + 6);
+        let player = ConnectionAuth::Player(sid(), 1, u64::MAX);
+
+        // seq 1 to 6 got dropped, a client that saw 6 misses nothing
+        assert_eq!(seqs(log.since(Some(log.epoch), 5, &player)), None);
+        let replayed = seqs(log.since(Some(log.epoch), 6, &player)).unwrap();
+        assert_eq!(replayed.len(), REPLAY_BUFFER_LEN);
+        assert_eq!(replayed[0], 7);
+    }
+
+    #[test]
+    fn session_log_replays_only_to_recipients() {
+        let mut log = SessionLog::new();
+        log.push(&sid(), "update.playerlist", None, None);
+        let whisper = serde_json::json!({ "text": "psst" });
+        log.push(
+            &sid(),
+            "chat.message",
+            Some(&whisper),
+            Some(vec![2].into_iter().collect()),
+        );
+        log.push(&sid(), "update.presence", None, None);
+
+        let since = |auth: ConnectionAuth| seqs(log.since(Some(log.epoch), 0, &auth));
+        assert_eq!(
+            since(ConnectionAuth::Player(sid(), 1, u64::MAX)),
+            Some(vec![1, 3])
+        );
+        assert_eq!(
+            since(ConnectionAuth::Player(sid(), 2, u64::MAX)),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            since(ConnectionAuth::Controller(u64::MAX)),
+            Some(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn prune_logs_drops_closed_and_idle_sessions() {
+        let (sender, mut handler) = handler();
+        let closed = SessionID::try_from("ZZ99YY88").unwrap();
+        handler.broadcast(sid(), "update.playerlist", None, None);
+        handler.broadcast(closed, "update.playerlist", None, None);
+
+        sender.send(Notification::SessionClosed(closed)).unwrap();
+        handler.handle_notifications();
+        assert!(!handler.session_logs.contains_key(&closed));
+        assert!(handler.session_logs.contains_key(&sid()));
+
+        // only looked for once per interval
+        let idle = Instant::now() + SESSION_LOG_IDLE;
+        handler.last_log_prune = idle;
+        handler.prune_logs(idle);
+        assert!(handler.session_logs.contains_key(&sid()));
+
+        let next_round = idle + SESSION_LOG_PRUNE_INTERVAL;
+        handler.prune_logs(next_round);
+        assert!(handler.session_logs.is_empty());
+
+        // the next event of the session starts a new log
+        handler.broadcast(closed, "update.playerlist", None, None);
+        assert_eq!(handler.session_logs[&closed].next_seq, 2);
+    }
+
+    fn config(queue_len: usize, overflow: OverflowPolicy) -> NotifierConfig {
+        NotifierConfig {
+            queue_len,
+            overflow,
+            ..Default::default()
+        }
+    }
+
+    fn frame(sid: &str, event: &str) -> QueuedFrame {
+        let sid = SessionID::try_from(sid).unwrap();
+        ServerFrame {
+            session_id: Some(sid.as_str()),
+            epoch: None,
+            seq: None,
+            event,
+            data: None,
+        }
+        .to_queued(Some(sid))
+    }
+
+    fn events(queue: &OutboundQueue) -> Vec<&str> {
+        queue.frames.iter().map(|f| f.event.as_str()).collect()
+    }
+
+    /// the server end of a websocket and the client reading from it
+    fn socket_pair() -> (WebSocket<TcpStream>, WebSocket<TcpStream>) {
+        use tungstenite::protocol::Role;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (
+            WebSocket::from_raw_socket(server, Role::Server, None),
+            WebSocket::from_raw_socket(client, Role::Client, None),
+        )
+    }
+
+    fn read_frame(client: &mut WebSocket<TcpStream>) -> serde_json::Value {
+        match client.read_message().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn drop_oldest_overflow_records_lost_session() {
+        let config = config(3, OverflowPolicy::DropOldest);
+        let mut queue = OutboundQueue::default();
+        for event in &["a", "b", "c", "d", "e"] {
+            assert!(queue.push(frame("AB12CD34", event), &config));
+        }
+
+        assert_eq!(events(&queue), vec!["c", "d", "e"]);
+        assert_eq!(queue.take_lost(), vec![sid()]);
+        assert!(queue.take_lost().is_empty());
+
+        // the resync covers the queued frames of the session, so nothing more gets lost
+        queue.push(frame("AB12CD34", RESYNC_EVENT), &config);
+        assert_eq!(events(&queue), vec![RESYNC_EVENT]);
+        assert!(queue.lost.is_empty());
+    }
+
+    #[test]
+    fn drop_oldest_overflow_resyncs_the_client() {
+        let (_sender, mut handler) = handler_with(config(4, OverflowPolicy::DropOldest));
+        let (server, mut client) = socket_pair();
+        handler.add_socket(WSConnection::new(
+            server,
+            ConnectionAuth::Player(sid(), 1, u64::MAX),
+        ));
+
+        for _ in 0..6 {
+            handler.broadcast(sid(), "update.playerlist", None, None);
+        }
+        assert_eq!(handler.connections[0].outbound.frames.len(), 4);
+        handler.flush();
+        handler.broadcast(sid(), "update.playerlist", None, None);
+        handler.flush();
+        assert!(!handler.needs_cleanup());
+
+        let resync = read_frame(&mut client);
+        assert_eq!(resync["event"], RESYNC_EVENT);
+        assert_eq!(resync["seq"], 6);
+        let next = read_frame(&mut client);
+        assert_eq!(next["event"], "update.playerlist");
+        assert_eq!(next["seq"], 7);
+    }
+
+    #[test]
+    fn coalesce_overflow_replaces_older_refresh() {
+        let config = config(3, OverflowPolicy::Coalesce);
+        let mut queue = OutboundQueue::default();
+        queue.push(frame("AB12CD34", "update.playerlist"), &config);
+        queue.push(frame("AB12CD34", "chat.message"), &config);
+        queue.push(frame("AB12CD34", "chat.message"), &config);
+
+        assert!(queue.push(frame("AB12CD34", "update.playerlist"), &config));
+        assert_eq!(
+            events(&queue),
+            vec!["chat.message", "chat.message", "update.playerlist"]
+        );
+        assert!(queue.lost.is_empty());
+
+        // nothing to replace, the refresh of another session falls back to dropping
+        assert!(queue.push(frame("ZZ99YY88", "update.playerlist"), &config));
+        assert_eq!(queue.frames.len(), 3);
+        assert_eq!(queue.take_lost(), vec![sid()]);
+    }
+
+    #[test]
+    fn disconnect_overflow_marks_socket_dead() {
+        let config = config(2, OverflowPolicy::Disconnect);
+        let mut queue = OutboundQueue::default();
+        assert!(queue.push(frame("AB12CD34", "a"), &config));
+        assert!(queue.push(frame("AB12CD34", "b"), &config));
+        assert!(!queue.push(frame("AB12CD34", "c"), &config));
+        assert_eq!(events(&queue), vec!["a", "b"]);
+
+        let (_sender, mut handler) = handler_with(config);
+        let (server, mut client) = socket_pair();
+        handler.add_socket(WSConnection::new(
+            server,
+            ConnectionAuth::Player(sid(), 1, u64::MAX),
+        ));
+        for _ in 0..3 {
+            handler.broadcast(sid(), "update.playerlist", None, None);
+        }
+        assert_eq!(handler.dead_sockets, vec![0]);
+
+        handler.clean_up();
+        assert!(handler.connections.is_empty());
+        match client.read_message().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Again),
+            other => panic!("expected close frame, got {:?}", other),
+        }
+    }
+
+    fn config_of(vars: &[(&str, &str)]) -> Result<NotifierConfig, String> {
+        NotifierConfig::from_vars(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| (*value).to_owned())
+        })
+    }
+
+    #[test]
+    fn config_reads_queue_len_and_overflow() {
+        let config = config_of(&[]).unwrap();
+        assert_eq!(config.queue_len, 32);
+        assert!(matches!(config.overflow, OverflowPolicy::Coalesce));
+        assert_eq!(config.bind, SocketAddr::from(([127, 0, 0, 1], 3031)));
+
+        let config = config_of(&[
+            ("WS_QUEUE_LEN", "4096"),
+            ("WS_OVERFLOW", "disconnect"),
+            ("WS_BIND", "0.0.0.0:4000"),
+        ])
+        .unwrap();
+        assert_eq!(config.queue_len, MAX_QUEUE_LEN);
+        assert!(matches!(config.overflow, OverflowPolicy::Disconnect));
+        assert_eq!(config.bind.port(), 4000);
+
+        let config = config_of(&[("WS_QUEUE_LEN", "1"), ("WS_OVERFLOW", "drop_oldest")]).unwrap();
+        assert_eq!(config.queue_len, 1);
+        assert!(matches!(config.overflow, OverflowPolicy::DropOldest));
+    }
+
+    #[test]
+    fn config_refuses_invalid_values() {
+        for vars in &[
+            [("WS_QUEUE_LEN", "0")],
+            [("WS_QUEUE_LEN", "4097")],
+            [("WS_QUEUE_LEN", "-1")],
+            [("WS_QUEUE_LEN", "lots")],
+            [("WS_OVERFLOW", "Coalesce")],
+            [("WS_OVERFLOW", "")],
+            [("WS_BIND", "3031")],
+        ] {
+            assert!(config_of(vars).is_err(), "{:?}", vars);
+        }
+    }
+}
@@ -1,13 +1,14 @@
 use rocket::response;
 use rocket::{Rocket, State};
 
-use log::{error, info, warn};
+use log::warn;
 
 use crate::api::auth::AdminAuthToken;
 use crate::api::auth::SessionID;
-use crate::database::Database;
+use crate::database::Storage;
+use std::sync::Arc;
 
-pub fn mount_controller_pages(mut rocket: Rocket) -> Rocket {
+pub fn mount_controller_pages(rocket: Rocket) -> Rocket {
     rocket.mount(
         "/ctrl",
         routes![
@@ -35,17 +36,18 @@ pub fn get_overview_page_err() -> response::Redirect {
 }
 
 #[get("/session/<sid>")]
+#[allow(clippy::result_large_err)]
 pub fn get_controller_page(
     _auth: AdminAuthToken,
     sid: SessionID,
-    db: State<Database>,
+    db: State<Arc<dyn Storage>>,
 ) -> Result<response::NamedFile, response::Redirect> {
-    match Database::get_session_data(&mut db.get_locked_conn(), &sid) {
-        Some(_) => Ok(response::NamedFile::open([crate::DIST_BASE,
+    match db.get_session_data(&sid) {
+        Ok(_) => Ok(response::NamedFile::open([crate::DIST_BASE,
         "admin_ui/session/session.html"].concat())
             .unwrap()),
-        None => {
-            warn!("Admin requested invalid session");
+        Err(e) => {
+            warn!("Admin requested invalid session: {}", e);
             Err(response::Redirect::to("ctrl/?error=InvalidSessionID"))
         }
     }
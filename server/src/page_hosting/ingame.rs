@@ -4,21 +4,23 @@ use rocket::{Rocket, State};
 use log::{error, info, warn};
 
 use crate::api::auth::PlayerAuthToken;
-use crate::database::Database;
+use crate::database::Storage;
+use std::sync::Arc;
 
-pub fn mount_ingame_pages(mut rocket: Rocket) -> Rocket {
+pub fn mount_ingame_pages(rocket: Rocket) -> Rocket {
     rocket.mount("/game", routes![get_game_page, get_game_page_noauth])
 }
 
 #[get("/")]
+#[allow(clippy::result_large_err)]
 pub fn get_game_page(
     auth: PlayerAuthToken,
-    db: State<Database>,
+    db: State<Arc<dyn Storage>>,
 ) -> Result<response::NamedFile, response::Redirect> {
     info!("New page request to session {}", auth.session_id);
 
-    if Database::get_session_data(&mut db.get_locked_conn(), &auth.session_id).is_none() {
-        warn!("Invalid session requested");
+    if let Err(e) = db.get_session_data(&auth.session_id) {
+        warn!("Invalid session requested: {}", e);
         Err(response::Redirect::to("/?error=InvalidSessionID"))
     //Err(response::Redirect::to("/"))
     } else {
@@ -0,0 +1,205 @@
+use crate::api::net_types::CleanupReport;
+use crate::database::{DbError, RetentionCutoffs, Storage};
+use crate::notify::{Notification, Notifier};
+use log::{error, info, warn};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// log target of the cleanup job
+pub const RETENTION_LOG_TARGET: &str = "Retention";
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+pub struct RetentionConfig {
+    /// active sessions without activity for this long get deactivated
+    pub session_idle: Duration,
+    /// inactive sessions get deleted after this, with their players, chat and game events.
+    /// The only place rows leave the otherwise append-only game_events log
+    pub closed_retention: Duration,
+    /// chat of inactive sessions gets pruned after this
+    pub chat_retention: Duration,
+    /// time between two runs of the job
+    pub interval: Duration,
+    /// only log what would be removed
+    pub dry_run: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            session_idle: Duration::from_secs(12 * HOUR),
+            closed_retention: Duration::from_secs(7 * DAY),
+            chat_retention: Duration::from_secs(7 * DAY),
+            interval: Duration::from_secs(HOUR),
+            dry_run: false,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// reads RETENTION_IDLE_HOURS, RETENTION_CLOSED_DAYS, RETENTION_CHAT_DAYS,
+    /// RETENTION_INTERVAL_MINUTES and RETENTION_DRY_RUN (true or false).
+    /// RETENTION_PLAYER_DAYS is the old name of RETENTION_CLOSED_DAYS
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = RetentionConfig::default();
+
+        if let Some(hours) = env_number("RETENTION_IDLE_HOURS")? {
+            config.session_idle = Duration::from_secs(hours * HOUR);
+        }
+        if let Some(days) = env_number("RETENTION_PLAYER_DAYS")? {
+            warn!(
+                target: RETENTION_LOG_TARGET,
+                "RETENTION_PLAYER_DAYS is deprecated, it deletes whole sessions. \
+                Use RETENTION_CLOSED_DAYS"
+            );
+            config.closed_retention = Duration::from_secs(days * DAY);
+        }
+        if let Some(days) = env_number("RETENTION_CLOSED_DAYS")? {
+            config.closed_retention = Duration::from_secs(days * DAY);
+        }
+        if let Some(days) = env_number("RETENTION_CHAT_DAYS")? {
+            config.chat_retention = Duration::from_secs(days * DAY);
+        }
+        if let Some(minutes) = env_number("RETENTION_INTERVAL_MINUTES")? {
+            if minutes == 0 {
+                return Err("RETENTION_INTERVAL_MINUTES must be at least 1".into());
+            }
+            config.interval = Duration::from_secs(minutes * 60);
+        }
+
+        if let Ok(dry_run) = std::env::var("RETENTION_DRY_RUN") {
+            config.dry_run = match dry_run.as_str() {
+                "true" => true,
+                "false" => false,
+                other => {
+                    return Err(format!(
+                        "RETENTION_DRY_RUN must be true or false, not {}",
+                        other
+                    ))
+                }
+            };
+        }
+
+        Ok(config)
+    }
+}
+
+fn env_number(name: &str) -> Result<Option<u64>, String> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} must be a positive number, not {}", name, value)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// the cleanup job, shared between its thread and the admin endpoint
+pub struct Retention {
+    config: RetentionConfig,
+    last_report: Mutex<Option<CleanupReport>>,
+}
+
+impl Retention {
+    pub fn new(config: RetentionConfig) -> Self {
+        info!(
+            target: RETENTION_LOG_TARGET,
+            "idle sessions closed after {}h, closed sessions deleted after {}d, chat after {}d{}",
+            config.session_idle.as_secs() / HOUR,
+            config.closed_retention.as_secs() / DAY,
+            config.chat_retention.as_secs() / DAY,
+            if config.dry_run { " (dry run)" } else { "" }
+        );
+        Retention {
+            config,
+            last_report: Mutex::new(None),
+        }
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.config.dry_run
+    }
+
+    /// result of the last run, by the job or an admin
+    pub fn last_report(&self) -> Option<CleanupReport> {
+        self.last_report.lock().unwrap().clone()
+    }
+
+    /// cleans up once and logs what was removed. Clients of closed and deleted sessions
+    /// get a session.closed event, controllers a new session list
+    pub fn run(
+        &self,
+        db: &dyn Storage,
+        notifier: &Notifier,
+        dry_run: bool,
+    ) -> Result<CleanupReport, DbError> {
+        let now = std::time::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        let cutoffs = RetentionCutoffs {
+            idle_before: now.saturating_sub(self.config.session_idle.as_secs()),
+            closed_before: now.saturating_sub(self.config.closed_retention.as_secs()),
+            chat_before: now.saturating_sub(self.config.chat_retention.as_secs()),
+        };
+
+        let report = db.cleanup(&cutoffs, dry_run)?;
+        info!(
+            target: RETENTION_LOG_TARGET,
+            "{}{} sessions deactivated, {} sessions with {} players and {} events deleted, \
+            {} messages deleted",
+            if dry_run { "dry run: " } else { "" },
+            report.sessions_deactivated,
+            report.sessions_deleted,
+            report.players_deleted,
+            report.events_deleted,
+            report.messages_deleted
+        );
+        *self.last_report.lock().unwrap() = Some(report.clone());
+
+        if !dry_run {
+            notify_closed(notifier, &report);
+        }
+        Ok(report)
+    }
+}
+
+fn notify_closed(notifier: &Notifier, report: &CleanupReport) {
+    for sid in report.deactivated.iter().chain(&report.deleted) {
+        notifier.send(Notification::CustomToSession(
+            *sid,
+            "session.closed".to_owned(),
+        ));
+    }
+    for sid in &report.deactivated {
+        notifier.send(Notification::SessionClosed(*sid));
+    }
+    for sid in &report.deleted {
+        notifier.send(Notification::SessionDeleted(*sid));
+    }
+    if !report.deactivated.is_empty() || !report.deleted.is_empty() {
+        notifier.send(Notification::UpdateSessionList);
+    }
+}
+
+/// runs the cleanup every interval until the server terminates
+pub fn start(
+    retention: Arc<Retention>,
+    db: Arc<dyn Storage>,
+    notifier: Notifier,
+) -> std::io::Result<()> {
+    std::thread::Builder::new()
+        .name("RetentionWorker".into())
+        .spawn(move || {
+            let mut next_run = Instant::now();
+            while !crate::SHOULD_TERMINATE.load(Ordering::Relaxed) {
+                if Instant::now() >= next_run {
+                    if let Err(e) = retention.run(&*db, &notifier, retention.dry_run()) {
+                        error!(target: RETENTION_LOG_TARGET, "Cleanup failed: {}", e);
+                    }
+                    next_run = Instant::now() + retention.config.interval;
+                }
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        })?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-import {getErrorMessage} from '../../src/errors'
+import {getErrorMessage, readApiError} from '../../src/errors'
 import {getCurrentTokenString, updateToken} from '../../src/utils'
 
 
@@ -72,8 +72,7 @@ window.addEventListener("load", () => {
         else {
             // no valid login
             console.error("user not allowed to log in")
-            const errMsg = await res.text()
-            alert(errMsg.length > 0 ? errMsg: "connect failed")
+            alert(await readApiError(res))
         }
     })
     
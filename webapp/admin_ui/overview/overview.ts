@@ -9,20 +9,34 @@ if (getCurrentTokenString() == null) {
 
 let sessionListDom: ExtendableList<SessionData>
 
+// bumped on every update, so a slow older update doesn't overwrite a newer one
+let sessionListGeneration = 0
+
 async function updateSessionList() {
 
     const token = getCurrentTokenString()
 
     if(!token) return
-    
 
-    const res = await apiFetch("/sessions/")
+    const generation = ++sessionListGeneration
+    let sessions: SessionData[] = []
+    let cursor: string | null = null
+
+    // follow next_cursor until the last page, the list shows every session
+    do {
+        const query: string = cursor == null ? "" : `?cursor=${encodeURIComponent(cursor)}`
+        const res = await apiFetch(`/sessions/${query}`)
+
+        if (res.status != 200 || res.headers.get("Content-Type") != "application/json") return
 
-    if (res.status == 200 && res.headers.get("Content-Type") == "application/json") {
-        let list = await res.json()
-        console.log(list)
-        sessionListDom.setData(list)
-    }
+        const page: SessionPage = await res.json()
+        if (generation != sessionListGeneration) return
+
+        sessions = sessions.concat(page.sessions)
+        cursor = page.next_cursor
+    } while (cursor != null)
+
+    sessionListDom.setData(sessions)
 }
 
 interface SessionData {
@@ -32,11 +46,18 @@ interface SessionData {
     player_count: number
 }
 
+/// newest sessions first, next_cursor is null on the last page
+interface SessionPage {
+    sessions: SessionData[],
+    next_cursor: string | null
+}
+
 window.addEventListener("load", () => {
     
     console.log("Loaded controller_overview")
 
     const notifications = new ServerNotifications(NotificationType.ControllerConnection)
+    notifications.subscribeOverview()
 
     notifications.registerEvent("controller.sessionlist", () => {
         console.log("got controller.sessionlist")
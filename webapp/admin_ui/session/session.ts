@@ -1,6 +1,8 @@
 import {ServerNotifications, NotificationType} from "../../src/websocket"
 import {getCurrentTokenString, apiFetch} from "../../src/utils"
 import { ExtendableList } from "../../src/ui"
+import { Presence, ChatMessage, SessionSettings, getSessionSettings, setSessionSettings, mutePlayer, getTranscript, TranscriptFormat } from "../../src/api"
+import { ChatView } from "../../src/chat"
 
 if (getCurrentTokenString() == null) {
     
@@ -8,6 +10,7 @@ if (getCurrentTokenString() == null) {
 }
 
 let playerListDom: ExtendableList<PlayerData>
+let chat: ChatView
 
 const currentSessionID: string = window.location.pathname.split("/")[3]
 
@@ -29,11 +32,66 @@ async function updatePlayerList() {
     }
 }
 
+async function loadSettings(form: HTMLFormElement) {
+    try {
+        const settings = await getSessionSettings(currentSessionID)
+        for (let key of Object.keys(settings)) {
+            const input = form.elements.namedItem(key) as HTMLInputElement | null
+            if (input) input.checked = settings[key]
+        }
+    } catch (error) {
+        console.error(error)
+    }
+}
+
+function initSettings() {
+    const form: HTMLFormElement = document.querySelector("#settings")
+    form.addEventListener("change", async () => {
+        const settings: SessionSettings = {
+            whispers_enabled: (form.elements.namedItem("whispers_enabled") as HTMLInputElement).checked,
+            announce_whispers: (form.elements.namedItem("announce_whispers") as HTMLInputElement).checked
+        }
+        try {
+            await setSessionSettings(currentSessionID, settings)
+        } catch (error) {
+            alert(error)
+            loadSettings(form)
+        }
+    })
+    loadSettings(form)
+}
+
+// how long the mute button silences a player
+const MUTE_DURATION_SECS = 5 * 60
+
+const EXPORT_EXTENSIONS: {[format in TranscriptFormat]: string} = {
+    "json": "json",
+    "markdown": "md",
+    "html": "html"
+}
+
+// the api needs the auth header, so the file gets downloaded through a blob url
+async function downloadTranscript(format: TranscriptFormat) {
+    try {
+        const blob = await getTranscript(currentSessionID, format)
+        const link = document.createElement("a")
+        link.href = URL.createObjectURL(blob)
+        link.download = `transcript-${currentSessionID}.${EXPORT_EXTENSIONS[format]}`
+        link.click()
+        // revoking right away can cancel the download in some browsers
+        setTimeout(() => URL.revokeObjectURL(link.href), 1000)
+    } catch (error) {
+        alert(error)
+    }
+}
+
 interface PlayerData {
+    user_id: number,
     name: string,
     joined: Date,
     state: string,
-    role: string
+    role: string,
+    presence: Presence
 }
 
 window.addEventListener("load", () => {
@@ -41,11 +99,24 @@ window.addEventListener("load", () => {
     console.log("Loaded controller_overview")
 
     const notifications = new ServerNotifications(NotificationType.ControllerConnection)
+    notifications.subscribeSession(currentSessionID)
 
     notifications.registerEvent("update.playerlist", () => {
         updatePlayerList()
     })
 
+    notifications.registerEvent("update.presence", () => {
+        updatePlayerList()
+    })
+
+    chat = new ChatView(document.querySelector("#chat"), currentSessionID)
+
+    notifications.registerEvent("chat.message", (msg?: ChatMessage) => {
+        // without data (resync) the whole chat gets reloaded
+        if (msg) chat.append(msg)
+        else chat.load()
+    })
+
     playerListDom = new ExtendableList<PlayerData>(document.querySelector("#player-list"),  el => {
         let root = document.createElement("div")
 
@@ -61,15 +132,44 @@ window.addEventListener("load", () => {
 
         let role = document.createElement("p")
         role.textContent = `Spieler: ${el.role}`
+
+        let presence = document.createElement("p")
+        if (el.presence.status == "online") {
+            presence.textContent = "Online"
+        } else if (el.presence.last_seen) {
+            presence.textContent = `Offline seit ${new Date(el.presence.last_seen * 1000).toLocaleString("DE-de")}`
+        } else {
+            presence.textContent = "Nie verbunden"
+        }
         
         root.appendChild(name)
         root.appendChild(joined)
         root.appendChild(state)
         root.appendChild(role)
+        root.appendChild(presence)
+
+        let mute = document.createElement("button")
+        mute.textContent = "5 Minuten stummschalten"
+        mute.addEventListener("click", async () => {
+            const reason = prompt("Grund (optional)") || undefined
+            try {
+                await mutePlayer(currentSessionID, el.user_id, MUTE_DURATION_SECS, reason)
+            } catch (error) {
+                alert(error)
+            }
+        })
+        root.appendChild(mute)
         return root
         
 
     }, {emptyMessage: "Keine Spieler in dieser Session", title: "Spieler"})
 
     updatePlayerList()
+    chat.load()
+    initSettings()
+
+    document.querySelectorAll("#export button").forEach(button => {
+        const format = (button as HTMLButtonElement).dataset.format as TranscriptFormat
+        button.addEventListener("click", () => downloadTranscript(format))
+    })
 })
\ No newline at end of file
@@ -1,4 +1,5 @@
 import {getCurrentTokenString} from './utils'
+import {readApiError} from './errors'
 
 export function rawApiFetch(url: string, params?: RequestInit): Promise<Response> {
     console.log(`Requesting api from ${url}`)
@@ -14,9 +15,17 @@ export function rawApiFetch(url: string, params?: RequestInit): Promise<Response
     return fetch(`/api/v1${url}`, params)
 }
 
+export interface Presence {
+    status: "online" | "offline",
+    // unix secs, only set if offline and the player was connected before
+    last_seen?: number | null
+}
+
 export interface PlayerData {
+    user_id: number,
     name: string,
-    role: object | null
+    role: object | null,
+    presence: Presence
 }
 
 export async function getPlayerList(sid: string): Promise<PlayerData[]> {
@@ -40,4 +49,98 @@ export interface SessionData {
     created: number
 }
 
-exp
\ No newline at end of file
+export type ChatChannel = "village" | "wolves" | "dead" | "whisper" | "system"
+
+export interface ChatMessage {
+    message_id: number,
+    // user_id of the player, null if sent by an admin
+    sender: number | null,
+    sender_name: string,
+    channel: ChatChannel,
+    // recipient of a whisper
+    to: number | null,
+    to_name: string | null,
+    // html escaped by the server
+    message: string,
+    send_date: number
+}
+
+// the newest messages, or the ones right before the message_id before
+export async function getChat(sid: string, before?: number): Promise<ChatMessage[]> {
+    const query = before != undefined ? `?before=${before}` : ""
+    const res = await rawApiFetch(`/sessions/${sid}/chat${query}`)
+
+    if (res.status == 200 && res.headers.get("Content-Type") == "application/json") {
+        return await res.json()
+    }
+
+    return Promise.reject(`failed to load chat from server: ${res.status} (${res.statusText})`)
+}
+
+// if to is set, the message is whispered to that player
+export async function postChat(sid: string, message: string, channel: ChatChannel, to?: number): Promise<ChatMessage> {
+    const res = await rawApiFetch(`/sessions/${sid}/chat`, {
+        method: "POST",
+        body: JSON.stringify({message, channel, to}),
+        headers: {
+            "Content-Type": "application/json"
+        }
+    })
+
+    if (res.status == 200) {
+        return await res.json()
+    }
+
+    return Promise.reject(await readApiError(res))
+}
+
+export interface SessionSettings {
+    whispers_enabled: boolean,
+    announce_whispers: boolean
+}
+
+export async function getSessionSettings(sid: string): Promise<SessionSettings> {
+    const res = await rawApiFetch(`/sessions/${sid}/settings`)
+
+    if (res.status == 200 && res.headers.get("Content-Type") == "application/json") {
+        return await res.json()
+    }
+
+    return Promise.reject(`failed to load settings from server: ${res.status} (${res.statusText})`)
+}
+
+export async function setSessionSettings(sid: string, settings: SessionSettings): Promise<void> {
+    const res = await rawApiFetch(`/sessions/${sid}/settings`, {
+        method: "PUT",
+        body: JSON.stringify(settings),
+        headers: {
+            "Content-Type": "application/json"
+        }
+    })
+
+    if (res.status != 200) return Promise.reject(await readApiError(res))
+}
+
+// admin only: the player can't post for the given time
+export async function mutePlayer(sid: string, userId: number, durationSecs: number, reason?: string): Promise<void> {
+    const res = await rawApiFetch(`/sessions/${sid}/chat/mutes/${userId}`, {
+        method: "PUT",
+        body: JSON.stringify({duration_secs: durationSecs, reason}),
+        headers: {
+            "Content-Type": "application/json"
+        }
+    })
+
+    if (res.status != 200) return Promise.reject(await readApiError(res))
+}
+
+export type TranscriptFormat = "json" | "markdown" | "html"
+
+// admin only: the complete chat including whispers and system events, rejects while the game runs
+export async function getTranscript(sid: string, format: TranscriptFormat): Promise<Blob> {
+    const res = await rawApiFetch(`/sessions/${sid}/transcript?format=${format}`)
+
+    if (res.status == 200) return await res.blob()
+
+    return Promise.reject(await readApiError(res))
+}
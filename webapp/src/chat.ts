@@ -0,0 +1,184 @@
+import * as api from "./api"
+
+// max message length, must match MAX_MESSAGE_LEN on the server
+const MAX_MESSAGE_LEN = 500
+
+const CHANNEL_NAMES: {[channel in api.ChatChannel]: string} = {
+    "village": "Dorf",
+    "wolves": "Werwölfe",
+    "dead": "Tote",
+    "whisper": "Flüstern",
+    "system": "System"
+}
+
+// channels nobody can pick to post in
+const READ_ONLY_CHANNELS = ["whisper", "system"]
+
+export class ChatView {
+    private sid: string
+    private messageList: HTMLUListElement
+    private loadOlderButton: HTMLButtonElement
+    private form: HTMLFormElement
+    private input: HTMLInputElement
+    private channelSelect: HTMLSelectElement
+    // only shown to players, admins read whispers but don't send them
+    private whisperSelect: HTMLSelectElement | null = null
+
+    constructor(root: HTMLDivElement, sid: string) {
+        this.sid = sid
+
+        root.classList.add("chat")
+
+        const header = document.createElement("h2")
+        header.textContent = "Chat"
+        root.appendChild(header)
+
+        this.loadOlderButton = document.createElement("button")
+        this.loadOlderButton.textContent = "Ältere Nachrichten laden"
+        this.loadOlderButton.addEventListener("click", () => this.loadOlder())
+        root.appendChild(this.loadOlderButton)
+
+        this.messageList = document.createElement("ul")
+        root.appendChild(this.messageList)
+
+        this.form = document.createElement("form")
+
+        // the server decides who may post where, it rejects everything else
+        this.channelSelect = document.createElement("select")
+        for (let channel of Object.keys(CHANNEL_NAMES)) {
+            if (READ_ONLY_CHANNELS.indexOf(channel) != -1) continue
+            const option = document.createElement("option")
+            option.value = channel
+            option.textContent = CHANNEL_NAMES[channel]
+            this.channelSelect.appendChild(option)
+        }
+        this.form.appendChild(this.channelSelect)
+
+        this.input = document.createElement("input")
+        this.input.maxLength = MAX_MESSAGE_LEN
+        this.input.placeholder = "Nachricht"
+        const send = document.createElement("button")
+        send.type = "submit"
+        send.textContent = "Senden"
+        this.form.appendChild(this.input)
+        this.form.appendChild(send)
+        root.appendChild(this.form)
+
+        this.form.addEventListener("submit", async event => {
+            event.preventDefault()
+            const message = this.input.value.trim()
+            if (message.length == 0) return
+
+            try {
+                // the server pushes the message back over the websocket
+                const to = this.whisperSelect && this.whisperSelect.value != ""
+                    ? Number(this.whisperSelect.value) : undefined
+                await api.postChat(this.sid, message, this.channelSelect.value as api.ChatChannel, to)
+                this.input.value = ""
+            } catch (error) {
+                alert(error)
+            }
+        })
+    }
+
+    // adds the recipient select, or removes it if whispers got disabled
+    setWhisperTargets(players: api.PlayerData[], ownName: string, enabled: boolean) {
+        if (!enabled) {
+            if (this.whisperSelect) this.form.removeChild(this.whisperSelect)
+            this.whisperSelect = null
+            return
+        }
+
+        if (!this.whisperSelect) {
+            this.whisperSelect = document.createElement("select")
+            this.form.insertBefore(this.whisperSelect, this.input)
+        }
+        const selected = this.whisperSelect.value
+        while (this.whisperSelect.lastElementChild) {
+            this.whisperSelect.removeChild(this.whisperSelect.lastElementChild)
+        }
+
+        const everyone = document.createElement("option")
+        everyone.value = ""
+        everyone.textContent = "an alle"
+        this.whisperSelect.appendChild(everyone)
+        for (let player of players) {
+            if (player.name == ownName) continue
+            const option = document.createElement("option")
+            option.value = player.user_id.toString()
+            option.textContent = `an ${player.name}`
+            this.whisperSelect.appendChild(option)
+        }
+        this.whisperSelect.value = selected
+        // the selected player may have left
+        if (this.whisperSelect.selectedIndex == -1) this.whisperSelect.value = ""
+    }
+
+    async load() {
+        try {
+            const messages = await api.getChat(this.sid)
+            this.clear()
+            for (let msg of messages) this.append(msg)
+            this.loadOlderButton.hidden = messages.length == 0
+        } catch (error) {
+            console.error(error)
+        }
+    }
+
+    private async loadOlder() {
+        const first = this.messageList.firstElementChild as HTMLLIElement | null
+        if (!first) return
+        try {
+            const messages = await api.getChat(this.sid, Number(first.dataset.messageId))
+            // prepend in reverse, so the oldest ends up at the top
+            for (let i = messages.length - 1; i >= 0; i--) {
+                this.messageList.insertBefore(this.createItem(messages[i]), this.messageList.firstElementChild)
+            }
+            this.loadOlderButton.hidden = messages.length == 0
+        } catch (error) {
+            console.error(error)
+        }
+    }
+
+    private clear() {
+        let last = this.messageList.lastElementChild
+        while(last) {
+            this.messageList.removeChild(last)
+            last = this.messageList.lastElementChild
+        }
+    }
+
+    append(msg: api.ChatMessage) {
+        const li = this.createItem(msg)
+        this.messageList.appendChild(li)
+        li.scrollIntoView()
+    }
+
+    private createItem(msg: api.ChatMessage): HTMLLIElement {
+        const li = document.createElement("li")
+        li.dataset.messageId = msg.message_id.toString()
+
+        const channel = document.createElement("span")
+        channel.classList.add("channel", msg.channel)
+        channel.textContent = `[${CHANNEL_NAMES[msg.channel]}]`
+
+        const sender = document.createElement("span")
+        sender.classList.add("sender")
+        sender.textContent = msg.to_name ? `${msg.sender_name} → ${msg.to_name}` : msg.sender_name
+        if (msg.sender == null) sender.classList.add("admin")
+
+        const time = document.createElement("span")
+        time.classList.add("time")
+        time.textContent = new Date(msg.send_date * 1000).toLocaleTimeString("DE-de")
+
+        const text = document.createElement("p")
+        // already escaped by the server
+        text.innerHTML = msg.message
+
+        li.appendChild(time)
+        li.appendChild(channel)
+        li.appendChild(sender)
+        li.appendChild(text)
+        return li
+    }
+}
@@ -8,4 +8,24 @@ export function getErrorMessage(errId: string) : string {
 const ErrorMap = {
     "NoToken": "Hast du vergessen dich zu verbinden? ;)",
     "InvalidSessionID": "Diese Session existiert nicht.",
-}
\ No newline at end of file
+    "TokenExpired": "Deine Anmeldung ist abgelaufen, bitte verbinde dich neu.",
+    "SessionClosed": "Die Session wurde wegen Inaktivität geschlossen.",
+}
+// body of every error response of the api
+export interface ApiError {
+    // machine readable, e.g. "not_found" or "muted"
+    code: string,
+    message: string,
+    details: object | null
+}
+
+// the message of an error response, the status if the body isn't an ApiError
+export async function readApiError(res: Response): Promise<string> {
+    try {
+        const err: ApiError = await res.json()
+        if (err.message) return err.message
+    } catch (e) {
+        // not json, e.g. from a proxy in front of the server
+    }
+    return `${res.status} (${res.statusText})`
+}
@@ -1,11 +1,30 @@
-import {getCurrentTokenString} from "./utils"
+import {getCurrentTokenString, getCurrentPlayerTokenData} from "./utils"
 
 
+// subprotocol offered next to the token, must match WS_AUTH_PROTOCOL on the server
+const AUTH_PROTOCOL = "werwolf.auth"
+
+// close code the server sends when the token ran out
+const CLOSE_TOKEN_EXPIRED = 4001
+
+// sent by the server if missed events couldn't be replayed, everything has to be reloaded
+const RESYNC_EVENT = "resync"
+
+const RECONNECT_DELAY_MS = 2000
+
 export enum NotificationType {
     PlayerConnection,
     ControllerConnection
 }
 
+interface ServerFrame {
+    session_id?: string,
+    epoch?: number,
+    seq?: number,
+    event: string,
+    data?: any
+}
+
 function getDisplayName(nt: NotificationType) : string {
     switch (nt) {
         case NotificationType.PlayerConnection:
@@ -17,37 +36,174 @@ function getDisplayName(nt: NotificationType) : string {
     }
 }
 
+interface SeqPosition {
+    epoch: number,
+    seq: number
+}
+
+// last seen epoch and sequence number per session, kept over page reloads.
+// The server starts a new epoch whenever the numbering starts over
+function getLastSeq(sid: string): SeqPosition | null {
+    const stored = sessionStorage.getItem(`lastSeq.${sid}`)
+    return stored == null ? null : JSON.parse(stored)
+}
+
+function setLastSeq(sid: string, epoch: number, seq: number) {
+    sessionStorage.setItem(`lastSeq.${sid}`, JSON.stringify({epoch, seq}))
+}
+
+interface WsInfo {
+    url: string | null,
+    // the websocket has its own port, not the one of the page
+    port: number
+}
+
+// asks the server where the notification websocket is served
+async function getWebSocketUrl(): Promise<string> {
+    const res = await fetch("/api/v1/ws")
+    const info: WsInfo = await res.json()
+    if (info.url) return info.url
+
+    const protocol = window.location.protocol == "https:" ? "wss:" : "ws:"
+    return `${protocol}//${window.location.hostname}:${info.port}/`
+}
+
 export class ServerNotifications {
     private ws: WebSocket
     private type: NotificationType
-    private eventCallbacks: Map<string, () => void>
+    private eventCallbacks: Map<string, (data?: any) => void>
+    // frames sent before the socket was open
+    private pendingFrames: string[] = []
+    // sessions to resume after a reconnect, for controllers the subscribed ones
+    private sessions: Set<string> = new Set()
+    private overview: boolean = false
 
     constructor(type: NotificationType) {
 
         this.type = type
+        this.eventCallbacks = new Map()
 
-        console.log(`Connectiong for notifications with type ${getDisplayName(type)}...`)
+        if (type == NotificationType.PlayerConnection) {
+            const tokenData = getCurrentPlayerTokenData()
+            if (tokenData) this.sessions.add(tokenData.session_id)
+        }
+
+        this.connect()
+    }
+
+    private async connect() {
+        console.log(`Connectiong for notifications with type ${getDisplayName(this.type)}...`)
         let token = getCurrentTokenString()
 
         if (token == null) throw "No token - not associated with a session or as admin"
-        this.ws = new WebSocket(`ws:localhost:3031/${token}`)   
+        let url: string
+        try {
+            url = await getWebSocketUrl()
+        } catch (error) {
+            console.error(error)
+            setTimeout(() => this.connect(), RECONNECT_DELAY_MS)
+            return
+        }
+
+        // the token is sent as subprotocol, so it never shows up in any url
+        this.ws = new WebSocket(url, [AUTH_PROTOCOL, token])
 
-        this.ws.onopen = console.log
+        this.ws.onopen = ev => {
+            console.log(ev)
+            // restore subscriptions and catch up on everything missed while disconnected
+            this.sessions.forEach(sid => {
+                if (this.type == NotificationType.ControllerConnection) {
+                    this.ws.send(JSON.stringify({type: "subscribe", session_id: sid}))
+                }
+                this.sendResume(sid)
+            })
+            if (this.overview) this.ws.send(JSON.stringify({type: "overview", enabled: true}))
+
+            for (let frame of this.pendingFrames) this.ws.send(frame)
+            this.pendingFrames = []
+        }
         this.ws.onerror = console.error
 
-        this.eventCallbacks = new Map()
+        this.ws.onclose = ev => {
+            console.log(`Notifications closed: ${ev.code} ${ev.reason}`)
+            if (ev.code == CLOSE_TOKEN_EXPIRED) {
+                const loginPage = this.type == NotificationType.ControllerConnection ? "/ctrl/" : "/"
+                window.location.assign(`${loginPage}?error=TokenExpired`)
+                return
+            }
+            setTimeout(() => this.connect(), RECONNECT_DELAY_MS)
+        }
 
         this.ws.onmessage = ev => {
-            const cb = this.eventCallbacks.get(ev.data)
-            console.log(`Notification Event: ${ev.data} | Callback: ${cb != undefined}`)
+            const frame: ServerFrame = JSON.parse(ev.data)
+            // sequence numbers may skip events this client isn't allowed to see,
+            // if frames get dropped the server sends a resync
+            if (frame.session_id != undefined && frame.epoch != undefined && frame.seq != undefined) {
+                setLastSeq(frame.session_id, frame.epoch, frame.seq)
+            }
+
+            if (frame.event == RESYNC_EVENT) {
+                console.log(`Notification resync of ${frame.session_id}`)
+                this.eventCallbacks.forEach(cb => cb())
+                return
+            }
+
+            const cb = this.eventCallbacks.get(frame.event)
+            console.log(`Notification Event: ${frame.event} | Callback: ${cb != undefined}`)
             if (cb != undefined) {
-                cb()
+                cb(frame.data)
             }
         }
     }
 
+    private sendResume(sid: string) {
+        const last = getLastSeq(sid)
+        // a plain number was stored before epochs existed, the server answers with a resync
+        if (last != null) {
+            const position = typeof last == "number" ? {epoch: null, seq: last} : last
+            this.ws.send(JSON.stringify({
+                type: "resume",
+                session_id: sid,
+                epoch: position.epoch,
+                last_seq: position.seq
+            }))
+        }
+    }
+
+    private isOpen(): boolean {
+        return this.ws != undefined && this.ws.readyState == WebSocket.OPEN
+    }
+
+    private sendFrame(frame: object) {
+        const data = JSON.stringify(frame)
+        if (this.isOpen()) this.ws.send(data)
+        else this.pendingFrames.push(data)
+    }
+
+    // controller only: receive the notifications of the session
+    subscribeSession(sid: string) {
+        if (this.sessions.has(sid)) return
+        this.sessions.add(sid)
+        if (this.isOpen()) {
+            this.sendFrame({type: "subscribe", session_id: sid})
+            this.sendResume(sid)
+        }
+    }
+
+    // controller only
+    unsubscribeSession(sid: string) {
+        this.sessions.delete(sid)
+        this.sendFrame({type: "unsubscribe", session_id: sid})
+    }
+
+    // controller only: receive controller.sessionlist events
+    subscribeOverview(enabled: boolean = true) {
+        this.overview = enabled
+        if (this.isOpen()) this.sendFrame({type: "overview", enabled})
+    }
+
     // returns false if callback for eventID was allready registered => still overwrite
-    registerEvent(eventID: string, callback: () => void): boolean {
+    registerEvent(eventID: string, callback: (data?: any) => void): boolean {
 
         const exists = this.eventCallbacks.has(eventID)
 
@@ -2,6 +2,7 @@ import {ServerNotifications, NotificationType} from "../../src/websocket"
 import {getCurrentTokenString, getCurrentPlayerTokenData} from "../../src/utils"
 import { ExtendableList } from "../../src/ui"
 import * as api from "../../src/api"
+import { ChatView } from "../../src/chat"
 
 if (getCurrentTokenString() == null) {
     
@@ -9,6 +10,7 @@ if (getCurrentTokenString() == null) {
 }
 
 let playerListDom: ExtendableList<api.PlayerData>
+let chat: ChatView
 
 async function updatePlayerList() {
 
@@ -16,6 +18,8 @@ async function updatePlayerList() {
     try {
         const list = await api.getPlayerList(tokenParsed.session_id)
         playerListDom.setData(list)
+        const settings = await api.getSessionSettings(tokenParsed.session_id)
+        chat.setWhisperTargets(list, tokenParsed.user_name, settings.whispers_enabled)
     } catch (error) {
         console.error(error)
     }
@@ -33,13 +37,36 @@ window.addEventListener("load", () => {
         updatePlayerList()
     })
 
+    notifications.registerEvent("update.settings", () => {
+        updatePlayerList()
+    })
+
+    notifications.registerEvent("update.presence", () => {
+        updatePlayerList()
+    })
+
+    // sent by the cleanup, nobody can join or play in the session anymore
+    notifications.registerEvent("session.closed", () => {
+        window.location.assign(`/?error=SessionClosed`)
+    })
+
+    chat = new ChatView(document.querySelector("#chat"), getCurrentPlayerTokenData().session_id)
+
+    notifications.registerEvent("chat.message", (msg?: api.ChatMessage) => {
+        // without data (resync) the whole chat gets reloaded
+        if (msg) chat.append(msg)
+        else chat.load()
+    })
+
     playerListDom = new ExtendableList<api.PlayerData>(document.querySelector("#playerlist"),  el => {
         let root = document.createElement("p")
 
         root.textContent = el.name
+        root.classList.toggle("offline", el.presence.status == "offline")
         return root
 
     }, {emptyMessage: "Keine Spieler verbunden", title: "Spieler"})
 
     updatePlayerList()
+    chat.load()
 })
\ No newline at end of file
@@ -1,4 +1,4 @@
-import {getErrorMessage} from '../../src/errors'
+import {getErrorMessage, readApiError} from '../../src/errors'
 import {getCurrentPlayerTokenData, updateToken} from '../../src/utils'
 
 window.addEventListener("load", async () => {
@@ -66,8 +66,7 @@ window.addEventListener("load", async () => {
         else {
             // no valid login
             console.error("user not allowed to log in")
-            const errMsg = await res.text()
-            alert(errMsg.length > 0 ? errMsg: "connect failed")
+            alert(await readApiError(res))
         }
     })
     